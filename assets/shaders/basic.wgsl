@@ -30,6 +30,9 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) uv: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) light: vec2<f32>,
+    @location(4) ao: f32,
+    @location(5) layer: u32,
 };
 
 struct VertexOutput {
@@ -37,6 +40,9 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_position: vec3<f32>,
     @location(2) world_normal: vec3<f32>,
+    @location(3) light: vec2<f32>,
+    @location(4) ao: f32,
+    @location(5) @interpolate(flat) layer: u32,
 };
 
 @vertex
@@ -48,11 +54,14 @@ fn vs_main(
     out.tex_coords = model.uv;
     out.world_position = model.position;
     out.world_normal = model.normal;
+    out.light = model.light;
+    out.ao = model.ao;
+    out.layer = model.layer;
     return out;
 }
 
 @group(1) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: texture_2d_array<f32>;
 @group(1) @binding(1)
 var s_diffuse: sampler;
 
@@ -63,6 +72,8 @@ const ambient_strength: f32 = 0.05;
 const atten_linear: f32 = 5.0;
 const atten_expo: f32 = 2.0;
 
+const block_light_color: vec3<f32> = vec3<f32>(1.0, 0.85, 0.6);
+
 fn calculate_light(view_dir: vec3<f32>, color: vec3<f32>, dir: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
     var result = color * min_light;
     let diffuse_strength = dot(normal, dir);
@@ -89,11 +100,19 @@ fn calculate_light(view_dir: vec3<f32>, color: vec3<f32>, dir: vec3<f32>, normal
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords, in.layer);
+    // Cutout textures, like flowers and glass, show what is behind their transparent pixels.
+    if object_color.a < 0.5 {
+        discard;
+    }
 
     let view_dir = normalize(camera.view_pos.xyz - in.world_position);
 
-    var total_light = vec3<f32>(0.0);
+    // Baked voxel light, with sunlight shaded a little by the directional light.
+    let sun_facing = max(dot(in.world_normal, normalize(dir_light.direction)), 0.0);
+    let sunlight = in.light.x * dir_light.color * (0.6 + 0.4 * sun_facing);
+    let block_light = in.light.y * block_light_color;
+    var total_light = max(sunlight, block_light);
     // var total_light = calculate_light(view_dir, dir_light.color, dir_light.direction, in.world_normal);
 
     for (var i = 0; i < MAX_POINT_LIGHTS; i++) {
@@ -108,7 +127,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         total_light += light_color;
     }
 
-    total_light *= object_color.xyz;
+    total_light *= in.ao * object_color.xyz;
 
     return vec4<f32>(total_light, object_color.a);
 }
@@ -5,6 +5,17 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
 };
 
+/// The ids `DeviceEvent::Button` reports for the left and right mouse buttons, which differ by
+/// platform.
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub const MOUSE_LEFT: u32 = 0;
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub const MOUSE_RIGHT: u32 = 1;
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const MOUSE_LEFT: u32 = 1;
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const MOUSE_RIGHT: u32 = 3;
+
 #[derive(Default)]
 pub struct Input {
     just_pressed: FastHashSet<KeyCode>,
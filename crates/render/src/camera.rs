@@ -1,5 +1,7 @@
 use glam::*;
 
+use crate::{frustum::Frustum, projection::Projection};
+
 #[derive(Copy, Clone)]
 pub struct Camera {
     pub pos: Vec3,
@@ -28,14 +30,19 @@ impl Camera {
         Vec3::new(-yaw_sin, 0.0, yaw_cos).normalize()
     }
 
-    pub fn view_matrix(&self) -> Mat4 {
+    /// The direction the camera is looking, including its pitch.
+    pub fn direction(&self) -> Vec3 {
         let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_to_rh(self.pos, self.direction(), Vec3::Y)
+    }
 
-        Mat4::look_to_rh(
-            self.pos,
-            Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vec3::Y,
-        )
+    /// The volume the camera sees through a projection.
+    pub fn frustum(&self, projection: &Projection) -> Frustum {
+        Frustum::from_matrix(projection.proj * self.view_matrix())
     }
 }
@@ -0,0 +1,79 @@
+use std::ops::AddAssign;
+
+use glam::{Mat4, Vec3, Vec4};
+
+/// An axis aligned box in world space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// The smallest box around every point, or `None` if there are no points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| {
+            Self::new(aabb.min.min(point), aabb.max.max(point))
+        }))
+    }
+}
+
+/// The volume a camera can see, bounded by six planes.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view projection matrix, which maps depth from 0 to 1 like wgpu.
+    pub fn from_matrix(view_proj: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes =
+            [w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    /// The left, right, bottom, top, near and far planes, each with its normal pointing inwards in
+    /// `xyz` and its distance in `w`. A point is in front of a plane when
+    /// `normal.dot(point) + distance` is positive.
+    pub fn planes(&self) -> &[Vec4; 6] {
+        &self.planes
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(point) + plane.w >= 0.0)
+    }
+
+    /// Whether any part of a box may be visible.
+    /// Boxes just outside a corner of the frustum can pass, but no visible box is ever rejected.
+    pub fn intersects(&self, aabb: &BoundingBox) -> bool {
+        self.planes.iter().all(|plane| {
+            // Only the corner furthest along the normal needs to be in front of the plane.
+            let normal = plane.truncate();
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// How many meshes were drawn and culled while rendering.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl AddAssign for CullStats {
+    fn add_assign(&mut self, other: Self) {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
@@ -2,10 +2,12 @@ pub mod renderer;
 
 pub mod camera;
 pub mod texture;
+pub mod texture_array;
 pub mod vertex;
 
 pub mod error;
 
+pub mod frustum;
 pub mod projection;
 
 pub mod dir_light;
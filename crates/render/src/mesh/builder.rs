@@ -21,12 +21,117 @@ impl MeshBuilder {
     }
 
     pub fn add(&mut self, coord: [f32; 3], face: usize) -> &mut Self {
-        // Push all vertice faces
-        for i in &VERTICES[face] {
-            self.vertices.push(*i + coord)
+        self.add_sized(coord, face, [1.0, 1.0, 1.0])
+    }
+
+    /// Adds a face stretched along each axis by `size`.
+    /// The uvs are stretched with it, so the texture repeats once per tile.
+    pub fn add_sized(&mut self, coord: [f32; 3], face: usize, size: [f32; 3]) -> &mut Self {
+        self.add_shaded(coord, face, size, 0, [1.0, 0.0], [1.0; 4])
+    }
+
+    /// Adds a sized face textured with a layer of a texture array, with baked sun and block light,
+    /// see [`Vertex::light`], and the ambient occlusion of each vertex, in the same order as
+    /// [`Self::corners`].
+    pub fn add_shaded(
+        &mut self,
+        coord: [f32; 3],
+        face: usize,
+        size: [f32; 3],
+        layer: u32,
+        light: [f32; 2],
+        ao: [f32; 4],
+    ) -> &mut Self {
+        let (u, v) = UV_AXES[face];
+
+        let vertices = VERTICES[face].map(|mut vertex| {
+            for (axis, size) in size.iter().enumerate() {
+                vertex.position[axis] *= size;
+            }
+            vertex.uv[0] *= size[u];
+            vertex.uv[1] *= size[v];
+            vertex
+        });
+        self.push_quad(vertices, coord, layer, light, ao)
+    }
+
+    /// Adds a face of a box inside a tile, where `min` and `max` run from 0 to 1 across the tile.
+    /// The uvs follow where the face lies in the tile, so a cut down face shows the matching part of
+    /// the texture instead of squashing all of it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_box_face(
+        &mut self,
+        coord: [f32; 3],
+        min: [f32; 3],
+        max: [f32; 3],
+        face: usize,
+        layer: u32,
+        light: [f32; 2],
+        ao: [f32; 4],
+    ) -> &mut Self {
+        let (u, v) = UV_AXES[face];
+
+        let vertices = VERTICES[face].map(|mut vertex| {
+            let corner = vertex.position;
+            for axis in 0..3 {
+                vertex.position[axis] = min[axis] + corner[axis] * (max[axis] - min[axis]);
+            }
+            // The uvs of the unit face either run along their axis or against it.
+            for (i, axis) in [u, v].into_iter().enumerate() {
+                vertex.uv[i] = match vertex.uv[i] == corner[axis] {
+                    true => vertex.position[axis],
+                    false => 1.0 - vertex.position[axis],
+                };
+            }
+            vertex
+        });
+        self.push_quad(vertices, coord, layer, light, ao)
+    }
+
+    /// Adds a quad through any four corners, in the same order as [`Self::corners`], so it faces
+    /// along `(corners[1] - corners[0]).cross(corners[2] - corners[0])`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_quad(
+        &mut self,
+        coord: [f32; 3],
+        corners: [[f32; 3]; 4],
+        uvs: [[f32; 2]; 4],
+        normal: [f32; 3],
+        layer: u32,
+        light: [f32; 2],
+        ao: [f32; 4],
+    ) -> &mut Self {
+        let vertices = std::array::from_fn(|i| {
+            let [x, y, z] = corners[i];
+            let [u, v] = uvs[i];
+            vertex(x, y, z, u, v, normal[0], normal[1], normal[2])
+        });
+        self.push_quad(vertices, coord, layer, light, ao)
+    }
+
+    fn push_quad(
+        &mut self,
+        vertices: [Vertex; 4],
+        coord: [f32; 3],
+        layer: u32,
+        light: [f32; 2],
+        ao: [f32; 4],
+    ) -> &mut Self {
+        for (mut vertex, ao) in vertices.into_iter().zip(ao) {
+            vertex.layer = layer;
+            vertex.light = light;
+            vertex.ao = ao;
+
+            self.vertices.push(vertex + coord)
         }
 
-        for i in &INDICES {
+        // Split the quad along the diagonal between its brighter corners, so the occlusion is
+        // interpolated evenly instead of streaking along the other diagonal.
+        let indices = match ao[0] + ao[3] > ao[1] + ao[2] {
+            true => &FLIPPED_INDICES,
+            false => &INDICES,
+        };
+        for i in indices {
             self.indices.push(*i + (4 * self.face_count))
         }
 
@@ -40,6 +145,16 @@ impl MeshBuilder {
         }
     }
 
+    /// Adds every face of another builder.
+    pub fn append(&mut self, other: MeshBuilder) -> &mut Self {
+        let base = self.vertices.len() as u32;
+        self.vertices.extend(other.vertices);
+        self.indices
+            .extend(other.indices.into_iter().map(|i| i + base));
+        self.face_count += other.face_count;
+        self
+    }
+
     pub fn with_translation(mut self, distance: [f32; 3]) -> Self {
         self.translate(distance);
         self
@@ -54,12 +169,29 @@ impl MeshBuilder {
         self
     }
 
+    /// The corners of a face on the unit cube, in vertex order.
+    pub fn corners(face: usize) -> [[f32; 3]; 4] {
+        VERTICES[face].map(|v| v.position)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn face_count(&self) -> u32 {
+        self.face_count
+    }
+
     pub fn build(self, renderer: &Renderer) -> Mesh {
         Mesh::new(renderer, &self.vertices, &self.indices)
     }
 }
 
+/// The axes the u and v coordinates of each face run along.
+/// Side faces run v down the y axis, so their textures stand upright.
+const UV_AXES: [(usize, usize); 6] = [(0, 2), (0, 2), (2, 1), (2, 1), (0, 1), (0, 1)];
 const INDICES: [u32; 6] = [0, 1, 2, 2, 1, 3];
+const FLIPPED_INDICES: [u32; 6] = [0, 1, 3, 0, 3, 2];
 const VERTICES: [[Vertex; 4]; 6] = [
     [
         // Top
@@ -77,30 +209,30 @@ const VERTICES: [[Vertex; 4]; 6] = [
     ],
     [
         // Left
-        vertex(0.0, 0.0, 1.0, 0.0, 1.0, -1.0, 0.0, 0.0),
-        vertex(0.0, 1.0, 1.0, 1.0, 1.0, -1.0, 0.0, 0.0),
-        vertex(0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0),
-        vertex(0.0, 1.0, 0.0, 1.0, 0.0, -1.0, 0.0, 0.0),
+        vertex(0.0, 0.0, 1.0, 1.0, 1.0, -1.0, 0.0, 0.0),
+        vertex(0.0, 1.0, 1.0, 1.0, 0.0, -1.0, 0.0, 0.0),
+        vertex(0.0, 0.0, 0.0, 0.0, 1.0, -1.0, 0.0, 0.0),
+        vertex(0.0, 1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0),
     ],
     [
         // Right
-        vertex(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+        vertex(1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0),
         vertex(1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0),
         vertex(1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0),
-        vertex(1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0),
+        vertex(1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0),
     ],
     [
         // Front
-        vertex(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0),
-        vertex(0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, -1.0),
-        vertex(1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0),
-        vertex(1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0),
+        vertex(0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0),
+        vertex(0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0),
+        vertex(1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -1.0),
+        vertex(1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0),
     ],
     [
         // Back
-        vertex(1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0),
-        vertex(1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0),
-        vertex(0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0),
-        vertex(0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0),
+        vertex(1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0),
+        vertex(1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0),
+        vertex(0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0),
+        vertex(0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0),
     ],
 ];
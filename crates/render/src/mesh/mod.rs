@@ -4,7 +4,9 @@ use wgpu::{
     ShaderModule,
 };
 
-use crate::{renderer::Renderer, texture::Texture, vertex::Vertex};
+use glam::Vec3;
+
+use crate::{frustum::BoundingBox, renderer::Renderer, texture::Texture, vertex::Vertex};
 
 pub mod builder;
 pub mod render;
@@ -13,6 +15,8 @@ pub struct Mesh {
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     num_indices: u32,
+    num_vertices: u32,
+    bounds: Option<BoundingBox>,
 }
 
 impl Mesh {
@@ -30,14 +34,31 @@ impl Mesh {
             usage: BufferUsages::INDEX,
         });
         let num_indices = indices.len() as u32;
+        let num_vertices = vertices.len() as u32;
+        let bounds = BoundingBox::from_points(vertices.iter().map(|v| Vec3::from(v.position)));
 
         Self {
             vertex_buffer,
             index_buffer,
             num_indices,
+            num_vertices,
+            bounds,
         }
     }
 
+    pub fn index_count(&self) -> u32 {
+        self.num_indices
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.num_vertices
+    }
+
+    /// The box around every vertex, or `None` for an empty mesh.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        self.bounds
+    }
+
     pub fn pipeline(
         topology: PrimitiveTopology,
         renderer: &Renderer,
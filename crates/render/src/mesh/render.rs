@@ -1,9 +1,36 @@
 use wgpu::RenderPass;
 
+use crate::frustum::{CullStats, Frustum};
+
 use super::Mesh;
 
 pub trait RenderMesh {
     fn render_mesh(&mut self, mesh: &Mesh);
+
+    /// Renders only the meshes which may be visible in the frustum. Empty meshes are skipped too.
+    fn render_visible<'m>(
+        &mut self,
+        meshes: impl IntoIterator<Item = &'m Mesh>,
+        frustum: &Frustum,
+    ) -> CullStats
+    where
+        Self: Sized,
+    {
+        let mut stats = CullStats::default();
+        for mesh in meshes {
+            match mesh
+                .bounds()
+                .is_some_and(|bounds| frustum.intersects(&bounds))
+            {
+                true => {
+                    self.render_mesh(mesh);
+                    stats.drawn += 1;
+                }
+                false => stats.culled += 1,
+            }
+        }
+        stats
+    }
 }
 
 impl<'pass> RenderMesh for RenderPass<'pass> {
@@ -19,7 +19,7 @@ impl ShaderTexture {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: tex.dimension,
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
                         },
                         count: None,
@@ -7,6 +7,9 @@ use crate::renderer::Renderer;
 pub enum TextureLoadError {
     #[error(transparent)]
     ImageError(#[from] ImageError),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
 }
 
 pub struct Texture {
@@ -14,6 +17,8 @@ pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// How shaders see the texture, which is `D2Array` for texture arrays.
+    pub dimension: wgpu::TextureViewDimension,
 }
 
 impl Texture {
@@ -64,10 +69,11 @@ impl Texture {
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Repeat so that stretched faces, like greedy meshed ones, tile their texture.
         let sampler = renderer.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
@@ -78,6 +84,7 @@ impl Texture {
             texture,
             view,
             sampler,
+            dimension: wgpu::TextureViewDimension::D2,
         })
     }
 
@@ -124,6 +131,7 @@ impl Texture {
             texture,
             view,
             sampler,
+            dimension: wgpu::TextureViewDimension::D2,
         }
     }
 }
@@ -0,0 +1,184 @@
+use std::{fs, path::Path};
+
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+use wgpu::naga::FastHashMap;
+
+use crate::{
+    renderer::Renderer,
+    texture::{Texture, TextureLoadError},
+};
+
+/// Packs equally sized images into the layers of a `texture_2d_array`.
+///
+/// Every image gets a layer of its own, so unlike an atlas no texture can bleed into its neighbor,
+/// whether from filtering, repeating across a stretched face, or sampling a smaller mip level.
+/// Mip levels are generated separately for each layer for the same reason.
+pub struct TextureArrayBuilder {
+    size: u32,
+    layers: Vec<RgbaImage>,
+    names: FastHashMap<String, u32>,
+}
+
+impl TextureArrayBuilder {
+    /// The layer shown for textures which weren't found.
+    pub const MISSING: u32 = 0;
+
+    /// Creates a builder for square layers `size` pixels wide, which must be a power of two.
+    /// Layer [`Self::MISSING`] is filled with a checkerboard.
+    pub fn new(size: u32) -> Self {
+        assert!(
+            size.is_power_of_two(),
+            "Texture array size should be a power of two"
+        );
+
+        let half = size / 2;
+        let missing = RgbaImage::from_fn(size, size, |x, y| match (x < half) == (y < half) {
+            true => Rgba([255, 0, 255, 255]),
+            false => Rgba([0, 0, 0, 255]),
+        });
+
+        Self {
+            size,
+            layers: vec![missing],
+            names: FastHashMap::default(),
+        }
+    }
+
+    /// Adds an image as a new layer, returning its index.
+    /// Images of the wrong size are scaled to fit. Adding a name again replaces the image in the
+    /// layer it already has.
+    pub fn add(&mut self, name: impl Into<String>, img: &DynamicImage) -> u32 {
+        let name = name.into();
+        let mut rgba = img.to_rgba8();
+        if rgba.dimensions() != (self.size, self.size) {
+            log::warn!(
+                "Texture `{name}` is {:?}, scaling it to {}x{}",
+                rgba.dimensions(),
+                self.size,
+                self.size
+            );
+            rgba = image::imageops::resize(&rgba, self.size, self.size, FilterType::Nearest);
+        }
+
+        if let Some(layer) = self.names.get(&name) {
+            self.layers[*layer as usize] = rgba;
+            return *layer;
+        }
+
+        let layer = self.layers.len() as u32;
+        self.layers.push(rgba);
+        self.names.insert(name, layer);
+        layer
+    }
+
+    pub fn add_bytes(
+        &mut self,
+        name: impl Into<String>,
+        bytes: &[u8],
+    ) -> Result<u32, TextureLoadError> {
+        Ok(self.add(name, &image::load_from_memory(bytes)?))
+    }
+
+    /// Adds every png in a directory, in name order, named after the file without its extension.
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> Result<(), TextureLoadError> {
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "png") {
+                files.push(path);
+            }
+        }
+
+        // Sort so that layers stay the same between runs.
+        files.sort();
+        for file in files {
+            let name = file.file_stem().unwrap().to_string_lossy().into_owned();
+            self.add(name, &image::open(&file)?);
+        }
+        Ok(())
+    }
+
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    /// The layer of every named texture.
+    pub fn layers(&self) -> &FastHashMap<String, u32> {
+        &self.names
+    }
+
+    pub fn build(self, renderer: &Renderer) -> Texture {
+        let mip_level_count = self.size.ilog2() + 1;
+        let size = wgpu::Extent3d {
+            width: self.size,
+            height: self.size,
+            depth_or_array_layers: self.layers.len() as u32,
+        };
+        let texture = renderer.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("texture array"),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, img) in self.layers.iter().enumerate() {
+            let mut mip = img.clone();
+            for level in 0..mip_level_count {
+                let width = mip.width();
+                renderer.queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    &mip,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * width),
+                        rows_per_image: Some(width),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height: width,
+                        depth_or_array_layers: 1,
+                    },
+                );
+
+                if width > 1 {
+                    mip = image::imageops::resize(&mip, width / 2, width / 2, FilterType::Triangle);
+                }
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Repeat so that stretched faces, like greedy meshed ones, tile their texture.
+        let sampler = renderer.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+            dimension: wgpu::TextureViewDimension::D2Array,
+        }
+    }
+}
@@ -6,6 +6,12 @@ pub struct Vertex {
     pub position: [f32; 3],
     pub uv: [f32; 2],
     pub normal: [f32; 3],
+    /// Baked sun and block light, from 0 to 1.
+    pub light: [f32; 2],
+    /// Ambient occlusion, from 0 for a fully occluded corner to 1 for an open one.
+    pub ao: f32,
+    /// The layer of the texture array to sample.
+    pub layer: u32,
 }
 
 impl Vertex {
@@ -29,6 +35,25 @@ impl Vertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2 + size_of::<[f32; 2]>())
+                        as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2 + size_of::<[f32; 2]>() * 2)
+                        as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2
+                        + size_of::<[f32; 2]>() * 2
+                        + size_of::<f32>()) as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -60,5 +85,8 @@ pub const fn vertex(
         position: [x, y, z],
         uv: [uvx, uvy],
         normal: [nx, ny, nz],
+        light: [1.0, 0.0],
+        ao: 1.0,
+        layer: 0,
     }
 }
@@ -1,41 +1,144 @@
+use std::cell::OnceCell;
+
 use render::{
     mesh::{builder::MeshBuilder, Mesh},
     renderer::Renderer,
 };
 
 macro_rules! conditional_tile {
-    ($self:expr, $loc:expr, $offset:expr, $builder:expr, $idx:expr, $pos_offset:expr) => {{
-        if let Some(pos) = loc($loc, $offset) {
-            if $self.get(pos).is_none() {
-                $builder.add(
-                    [
-                        $loc[0] as f32 + $pos_offset[0],
-                        $loc[1] as f32 + $pos_offset[1],
-                        $loc[2] as f32 + $pos_offset[2],
-                    ],
-                    $idx,
-                );
-            }
-        } else {
-            $builder.add(
+    ($self:expr, $registry:expr, $neighbors:expr, $tile:expr, $state:expr, $loc:expr, $builder:expr, $idx:expr, $pos_offset:expr) => {{
+        if $self.face_visible($registry, $neighbors, $tile, $loc, $idx) {
+            $builder.add_shaded(
                 [
                     $loc[0] as f32 + $pos_offset[0],
                     $loc[1] as f32 + $pos_offset[1],
                     $loc[2] as f32 + $pos_offset[2],
                 ],
                 $idx,
+                [1.0; 3],
+                $registry.texture_layer($tile, $state, $idx),
+                crate::light::vertex_light($self.face_light($neighbors, $loc, $idx)),
+                crate::mesher::vertex_ao($self.face_ao($registry, $neighbors, $loc, $idx)),
             );
         }
     }};
 }
 
-use crate::tile::Tile;
+use crate::{
+    fluid,
+    light::{LightChannel, MAX_LIGHT},
+    lod,
+    mesher::{self, MeshMode},
+    palette::PalettedStorage,
+    shape::{self, opposite},
+    tile::{TileId, TileRegistry, TileState},
+};
 
 pub const CHUNK_SIZE: usize = 32;
-const CHUNK_VOLUME: usize = CHUNK_SIZE.pow(3);
+pub(crate) const CHUNK_VOLUME: usize = CHUNK_SIZE.pow(3);
+
+/// The direction of each face, in the same order as `MeshBuilder`.
+pub const FACE_OFFSETS: [[i32; 3]; 6] = [
+    // Top
+    [0, 1, 0],
+    // Bottom
+    [0, -1, 0],
+    // Left
+    [-1, 0, 0],
+    // Right
+    [1, 0, 0],
+    // Front
+    [0, 0, -1],
+    // Back
+    [0, 0, 1],
+];
+
+/// The number of chunks around a chunk, counting the chunk itself.
+pub(crate) const NEIGHBORHOOD: usize = 27;
+
+/// The chunks around a chunk, including those only touching its edges and corners, which ambient
+/// occlusion looks into.
+#[derive(Default, Clone)]
+pub struct ChunkNeighbors<'a> {
+    chunks: [Option<&'a Chunk>; NEIGHBORHOOD],
+    /// The level of detail each face neighbor is drawn at, see [`crate::lod`].
+    lods: [u8; 6],
+    /// The downsampled layer of each face neighbor touching the chunk, built on first use.
+    borders: [OnceCell<Vec<Option<TileId>>>; 6],
+}
+
+impl<'a> ChunkNeighbors<'a> {
+    /// Creates neighbors from the chunks touching each face, in the same order as [`FACE_OFFSETS`].
+    pub fn new(faces: [Option<&'a Chunk>; 6]) -> Self {
+        let mut neighbors = Self::default();
+        for (face, chunk) in faces.into_iter().enumerate() {
+            neighbors.chunks[neighbor_index(FACE_OFFSETS[face])] = chunk;
+        }
+        neighbors
+    }
+
+    /// Creates neighbors by looking up the chunk at every offset from `[-1, -1, -1]` to `[1, 1, 1]`.
+    pub fn from_fn(mut f: impl FnMut([i32; 3]) -> Option<&'a Chunk>) -> Self {
+        Self {
+            chunks: std::array::from_fn(|i| f(neighbor_offset(i))),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the level of detail each face neighbor is drawn at, in the same order as
+    /// [`FACE_OFFSETS`], so faces on the border are culled against what is actually drawn there.
+    pub fn with_lods(mut self, lods: [u8; 6]) -> Self {
+        self.lods = lods;
+        self.borders = Default::default();
+        self
+    }
 
+    pub fn lod(&self, face: usize) -> u8 {
+        self.lods[face]
+    }
+
+    pub fn get(&self, face: usize) -> Option<&'a Chunk> {
+        self.at(FACE_OFFSETS[face])
+    }
+
+    pub fn at(&self, offset: [i32; 3]) -> Option<&'a Chunk> {
+        self.chunks[neighbor_index(offset)]
+    }
+
+    /// The cells of a face neighbor's layer touching the chunk, at the neighbor's level of detail,
+    /// see [`lod::border_layer`]. The layer is only downsampled once per set of neighbors.
+    pub(crate) fn border_layer(
+        &self,
+        registry: &TileRegistry,
+        face: usize,
+    ) -> Option<&[Option<TileId>]> {
+        let neighbor = self.get(face)?;
+        let layer = self.borders[face]
+            .get_or_init(|| lod::border_layer(neighbor, registry, self.lods[face], face));
+        Some(layer)
+    }
+}
+
+pub(crate) fn neighbor_index(offset: [i32; 3]) -> usize {
+    ((offset[0] + 1) + (offset[1] + 1) * 3 + (offset[2] + 1) * 9) as usize
+}
+
+pub(crate) fn neighbor_offset(i: usize) -> [i32; 3] {
+    let i = i as i32;
+    [i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1]
+}
+
+#[derive(Clone)]
 pub struct Chunk {
-    tiles: Vec<Option<Tile>>,
+    tiles: PalettedStorage<Option<TileId>>,
+    /// Sun and block light of each tile, packed as described by [`LightChannel`].
+    light: PalettedStorage<u8>,
+    /// How far each fluid tile has flowed from its source, see [`crate::fluid`].
+    fluid: PalettedStorage<u8>,
+    /// The state of each tile, see [`TileState`].
+    states: PalettedStorage<TileState>,
+
+    mesh_mode: MeshMode,
 }
 
 impl Default for Chunk {
@@ -46,43 +149,342 @@ impl Default for Chunk {
 
 impl Chunk {
     pub fn new() -> Self {
-        let tiles = Vec::from_iter(std::iter::repeat_with(|| None).take(CHUNK_VOLUME));
-        Self { tiles }
+        Self {
+            tiles: PalettedStorage::new(CHUNK_VOLUME, None),
+            light: PalettedStorage::new(CHUNK_VOLUME, 0),
+            fluid: PalettedStorage::new(CHUNK_VOLUME, 0),
+            states: PalettedStorage::new(CHUNK_VOLUME, TileState::default()),
+            mesh_mode: MeshMode::default(),
+        }
+    }
+
+    /// Sets a tile in its default state, which starts out as a fluid source if it is a fluid.
+    pub fn set(&mut self, loc: [usize; 3], tile: Option<TileId>) {
+        let i = loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE;
+        self.tiles.set(i, tile);
+        if self.fluid.get(i) != 0 {
+            self.fluid.set(i, 0);
+        }
+        if self.states.get(i) != TileState::default() {
+            self.states.set(i, TileState::default());
+        }
+    }
+
+    pub fn state(&self, loc: [usize; 3]) -> TileState {
+        self.states
+            .get(loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE)
+    }
+
+    pub fn set_state(&mut self, loc: [usize; 3], state: TileState) {
+        self.states.set(
+            loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE,
+            state,
+        );
+    }
+
+    /// The stored flow of a fluid tile, where 0 is a source. See [`crate::fluid::fluid_level`].
+    pub fn fluid(&self, loc: [usize; 3]) -> u8 {
+        self.fluid
+            .get(loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE)
+    }
+
+    pub fn set_fluid(&mut self, loc: [usize; 3], fluid: u8) {
+        self.fluid.set(
+            loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE,
+            fluid,
+        );
+    }
+
+    pub fn get(&self, loc: [usize; 3]) -> Option<TileId> {
+        self.tiles
+            .get(loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE)
     }
 
-    pub fn set(&mut self, loc: [usize; 3], tile: Option<Tile>) {
-        self.tiles[loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE] = tile;
+    /// The light level of a tile. Chunks start out dark until they are lit, see [`crate::light`].
+    pub fn light(&self, loc: [usize; 3], channel: LightChannel) -> u8 {
+        channel.unpack(self.packed_light(loc))
     }
 
-    pub fn get(&self, loc: [usize; 3]) -> Option<&Tile> {
-        self.tiles[loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE].as_ref()
+    pub fn set_light(&mut self, loc: [usize; 3], channel: LightChannel, level: u8) {
+        let i = loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE;
+        self.light.set(i, channel.pack(self.light.get(i), level));
     }
 
-    pub fn mesh(&self, renderer: &Renderer, offset: [f32; 3]) -> Mesh {
+    pub(crate) fn packed_light(&self, loc: [usize; 3]) -> u8 {
+        self.light
+            .get(loc[0] + loc[1] * CHUNK_SIZE + loc[2] * CHUNK_SIZE * CHUNK_SIZE)
+    }
+
+    /// The packed light shining on a face, which is the light of the tile the face looks into.
+    /// Faces looking into an unloaded chunk are fully sunlit.
+    pub(crate) fn face_light(
+        &self,
+        neighbors: &ChunkNeighbors,
+        loc: [usize; 3],
+        face: usize,
+    ) -> u8 {
+        let offset = FACE_OFFSETS[face];
+        match self::loc(loc, offset) {
+            Some(pos) => self.packed_light(pos),
+            None => match neighbors.get(face) {
+                Some(chunk) => chunk.packed_light(wrap(loc, offset)),
+                None => LightChannel::Sun.pack(0, MAX_LIGHT),
+            },
+        }
+    }
+
+    /// The ambient occlusion of each corner of a face, in the vertex order of `MeshBuilder`, from 0
+    /// for a fully occluded corner to 3 for an open one.
+    pub(crate) fn face_ao(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        loc: [usize; 3],
+        face: usize,
+    ) -> [u8; 4] {
+        let normal = FACE_OFFSETS[face];
+        let axis = normal.iter().position(|v| *v != 0).unwrap();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let front = [0, 1, 2].map(|i| loc[i] as i32 + normal[i]);
+
+        MeshBuilder::corners(face).map(|corner| {
+            // Each corner is darkened by the two tiles beside it and the one diagonal to it.
+            let mut side_u = front;
+            side_u[u] += if corner[u] > 0.5 { 1 } else { -1 };
+            let mut side_v = front;
+            side_v[v] += if corner[v] > 0.5 { 1 } else { -1 };
+            let mut diagonal = side_u;
+            diagonal[v] = side_v[v];
+
+            let occludes = |pos| self.occludes_at(registry, neighbors, pos) as u8;
+            match (occludes(side_u), occludes(side_v)) {
+                (1, 1) => 0,
+                (a, b) => 3 - a - b - occludes(diagonal),
+            }
+        })
+    }
+
+    /// Whether the tile at a location, which may lie in a neighboring chunk, occludes light.
+    fn occludes_at(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        pos: [i32; 3],
+    ) -> bool {
+        self.tile_at(neighbors, pos)
+            .is_some_and(|t| registry.get(t).occludes())
+    }
+
+    /// The tile at a location which may lie in a neighboring chunk, or air if that isn't loaded.
+    pub(crate) fn tile_at(&self, neighbors: &ChunkNeighbors, pos: [i32; 3]) -> Option<TileId> {
+        let size = CHUNK_SIZE as i32;
+        let offset = pos.map(|v| v.div_euclid(size));
+        let loc = pos.map(|v| v.rem_euclid(size) as usize);
+
+        match offset {
+            [0, 0, 0] => self.get(loc),
+            _ => neighbors.at(offset).and_then(|chunk| chunk.get(loc)),
+        }
+    }
+
+    pub(crate) fn from_storage(tiles: PalettedStorage<Option<TileId>>) -> Self {
+        Self {
+            tiles,
+            light: PalettedStorage::new(CHUNK_VOLUME, 0),
+            fluid: PalettedStorage::new(CHUNK_VOLUME, 0),
+            states: PalettedStorage::new(CHUNK_VOLUME, TileState::default()),
+            mesh_mode: MeshMode::default(),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> &PalettedStorage<Option<TileId>> {
+        &self.tiles
+    }
+
+    pub(crate) fn fluid_storage(&self) -> &PalettedStorage<u8> {
+        &self.fluid
+    }
+
+    pub(crate) fn set_fluid_storage(&mut self, fluid: PalettedStorage<u8>) {
+        self.fluid = fluid;
+    }
+
+    pub(crate) fn state_storage(&self) -> &PalettedStorage<TileState> {
+        &self.states
+    }
+
+    pub(crate) fn set_state_storage(&mut self, states: PalettedStorage<TileState>) {
+        self.states = states;
+    }
+
+    /// Sets every tile in the chunk, in its default state.
+    pub fn fill(&mut self, tile: Option<TileId>) {
+        self.tiles.fill(tile);
+        self.fluid.fill(0);
+        self.states.fill(TileState::default());
+    }
+
+    /// Returns the tile filling the whole chunk, if it only holds one kind of tile.
+    /// `Some(None)` is a chunk of only air.
+    pub fn uniform(&self) -> Option<Option<TileId>> {
+        self.tiles.uniform()
+    }
+
+    /// Shrinks the storage of tiles, light, fluid levels and states after values were removed.
+    pub fn compact(&mut self) {
+        self.tiles.compact();
+        self.light.compact();
+        self.fluid.compact();
+        self.states.compact();
+    }
+
+    /// The number of bytes used by the chunk, including its heap allocations.
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.tiles.heap_usage()
+            + self.light.heap_usage()
+            + self.fluid.heap_usage()
+            + self.states.heap_usage()
+    }
+
+    pub fn mesh_mode(&self) -> MeshMode {
+        self.mesh_mode
+    }
+
+    /// Selects the mesher used the next time this chunk is meshed.
+    pub fn set_mesh_mode(&mut self, mode: MeshMode) {
+        self.mesh_mode = mode;
+    }
+
+    /// Meshes the chunk, culling faces hidden by tiles in this chunk or in its neighbors.
+    /// Faces on a border with an unloaded neighbor are always emitted.
+    pub fn mesh(
+        &self,
+        renderer: &Renderer,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        offset: [f32; 3],
+    ) -> Mesh {
+        self.build_mesh(registry, neighbors, offset).build(renderer)
+    }
+
+    /// Builds the vertices of the chunk's mesh without uploading them.
+    pub fn build_mesh(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        offset: [f32; 3],
+    ) -> MeshBuilder {
         let mut builder = MeshBuilder::default();
+        if self.uniform() == Some(None) {
+            return builder;
+        }
+
+        match self.mesh_mode {
+            MeshMode::Simple => self.mesh_simple(registry, neighbors, &mut builder, offset),
+            MeshMode::Greedy => mesher::greedy(self, registry, neighbors, &mut builder, offset),
+        }
+        shape::mesh(self, registry, neighbors, &mut builder, offset);
+        fluid::mesh(self, registry, neighbors, &mut builder, offset);
+        builder
+    }
+
+    /// Meshes the chunk downsampled to a level of detail, see [`crate::lod`].
+    /// Level 0 is full detail, meshed with the chunk's mesh mode.
+    pub fn build_lod_mesh(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        level: u8,
+        offset: [f32; 3],
+    ) -> MeshBuilder {
+        if level == 0 {
+            return self.build_mesh(registry, neighbors, offset);
+        }
+
+        let mut builder = MeshBuilder::default();
+        if self.uniform() == Some(None) {
+            return builder;
+        }
+        lod::mesh(self, registry, neighbors, level, &mut builder, offset);
+        builder
+    }
+
+    fn mesh_simple(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        builder: &mut MeshBuilder,
+        offset: [f32; 3],
+    ) {
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
                     let i = x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE;
-                    if self.tiles[i].is_some() {
-                        // Top
-                        conditional_tile!(self, [x, y, z], [0, 1, 0], builder, 0, offset);
-                        // Bottom
-                        conditional_tile!(self, [x, y, z], [0, -1, 0], builder, 1, offset);
-                        // Left
-                        conditional_tile!(self, [x, y, z], [-1, 0, 0], builder, 2, offset);
-                        // Right
-                        conditional_tile!(self, [x, y, z], [1, 0, 0], builder, 3, offset);
-                        // Front
-                        conditional_tile!(self, [x, y, z], [0, 0, -1], builder, 4, offset);
-                        // Back
-                        conditional_tile!(self, [x, y, z], [0, 0, 1], builder, 5, offset);
+                    if let Some(tile) = self
+                        .tiles
+                        .get(i)
+                        .filter(|tile| mesher::drawn_as_cube(registry, *tile))
+                    {
+                        for face in 0..FACE_OFFSETS.len() {
+                            conditional_tile!(
+                                self,
+                                registry,
+                                neighbors,
+                                tile,
+                                self.states.get(i),
+                                [x, y, z],
+                                builder,
+                                face,
+                                offset
+                            );
+                        }
                     }
                 }
             }
         }
+    }
+
+    /// Whether the face of `tile` at `loc` can be seen, looking into the neighboring chunk if needed.
+    pub(crate) fn face_visible(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        tile: TileId,
+        loc: [usize; 3],
+        face: usize,
+    ) -> bool {
+        self.side_visible(registry, neighbors, tile, loc, face, FULL_SIDE)
+    }
+
+    /// Whether the part `rect` of the side of `tile` at `loc` facing `face` can be seen, as in
+    /// [`side_visible`], looking into the neighboring chunk if needed.
+    pub(crate) fn side_visible(
+        &self,
+        registry: &TileRegistry,
+        neighbors: &ChunkNeighbors,
+        tile: TileId,
+        loc: [usize; 3],
+        face: usize,
+        rect: [f32; 4],
+    ) -> bool {
+        let offset = FACE_OFFSETS[face];
+        match (self::loc(loc, offset), neighbors.lod(face)) {
+            (Some(pos), _) => side_visible(registry, tile, self.tile_state(pos), face, rect),
+            // The neighbor lies in the next chunk over, so check that chunk if it is loaded, at the
+            // level of detail it is drawn at.
+            (None, 0) => {
+                let neighbor = neighbors
+                    .get(face)
+                    .and_then(|c| c.tile_state(wrap(loc, offset)));
+                side_visible(registry, tile, neighbor, face, rect)
+            }
+            (None, _) => lod::border_visible(registry, neighbors, tile, loc, 1, face),
+        }
+    }
 
-        builder.build(renderer)
+    pub(crate) fn tile_state(&self, loc: [usize; 3]) -> Option<(TileId, TileState)> {
+        Some((self.get(loc)?, self.state(loc)))
     }
 }
 
@@ -99,3 +501,51 @@ fn loc(pos: [usize; 3], offset: [i32; 3]) -> Option<[usize; 3]> {
     }
     Some([pos[0] as usize, pos[1] as usize, pos[2] as usize])
 }
+
+/// Offsets a location, wrapping it around into the next chunk.
+fn wrap(pos: [usize; 3], offset: [i32; 3]) -> [usize; 3] {
+    let size = CHUNK_SIZE as i32;
+    [
+        (pos[0] as i32 + offset[0]).rem_euclid(size) as usize,
+        (pos[1] as i32 + offset[1]).rem_euclid(size) as usize,
+        (pos[2] as i32 + offset[2]).rem_euclid(size) as usize,
+    ]
+}
+
+/// The whole side of a tile, as a rectangle for [`side_visible`].
+pub(crate) const FULL_SIDE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+/// Whether a face of a full cube of `tile` shows against a full cube of `neighbor`, like the cells
+/// of a level of detail mesh.
+pub(crate) fn face_visible(
+    registry: &TileRegistry,
+    tile: TileId,
+    neighbor: Option<TileId>,
+) -> bool {
+    match neighbor {
+        None => true,
+        // Transparent tiles of the same type merge together, like glass or water.
+        Some(neighbor) if neighbor == tile => false,
+        Some(neighbor) => !registry.get(neighbor).opaque(),
+    }
+}
+
+/// Whether the part of the side of `tile` facing `face` given by `rect`, over the side's axes in
+/// the same form as [`crate::shape::TileModel`], shows against `neighbor`.
+/// It is only hidden where the neighbor's model covers all of it.
+pub(crate) fn side_visible(
+    registry: &TileRegistry,
+    tile: TileId,
+    neighbor: Option<(TileId, TileState)>,
+    face: usize,
+    rect: [f32; 4],
+) -> bool {
+    match neighbor {
+        None => true,
+        // Transparent tiles of the same type merge together, like glass or water.
+        Some((neighbor, state)) if neighbor == tile || registry.get(neighbor).opaque() => {
+            !registry.model(neighbor, state).covers(opposite(face), rect)
+        }
+        Some(_) => true,
+    }
+}
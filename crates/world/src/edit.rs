@@ -0,0 +1,631 @@
+use glam::IVec3;
+use thiserror::Error;
+use wgpu::naga::FastHashSet;
+
+use crate::{
+    chunk::FACE_OFFSETS,
+    tile::{TileDef, TileId, TileRegistry, TileState},
+    world::World,
+};
+
+/// The names of the faces of [`FACE_OFFSETS`], as used by state properties like `facing`.
+const FACE_NAMES: [&str; 6] = ["top", "bottom", "left", "right", "front", "back"];
+const AXIS_NAMES: [&str; 3] = ["x", "y", "z"];
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum EditError {
+    #[error("Chunk {0} is not loaded")]
+    Unloaded(IVec3),
+}
+
+/// A tile along with its state, or air.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Voxel {
+    pub tile: Option<TileId>,
+    pub state: TileState,
+}
+
+impl Voxel {
+    pub const AIR: Self = Self {
+        tile: None,
+        state: TileState(0),
+    };
+
+    pub fn new(tile: Option<TileId>, state: TileState) -> Self {
+        Self { tile, state }
+    }
+}
+
+/// The shape of the area a bulk edit covers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Brush {
+    /// Every tile between two corners, including both.
+    Box { min: IVec3, max: IVec3 },
+    /// Every tile whose center is within `radius` of the center of `center`.
+    Sphere { center: IVec3, radius: f32 },
+    /// An upright cylinder standing on `base`, `height` tiles tall.
+    Cylinder {
+        base: IVec3,
+        radius: f32,
+        height: i32,
+    },
+}
+
+impl Brush {
+    /// The corners of the box around the brush, including both.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        match *self {
+            Self::Box { min, max } => (min.min(max), min.max(max)),
+            Self::Sphere { center, radius } => {
+                let r = IVec3::splat(radius.max(0.0) as i32);
+                (center - r, center + r)
+            }
+            Self::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let r = radius.max(0.0) as i32;
+                (
+                    base - IVec3::new(r, 0, r),
+                    base + IVec3::new(r, height - 1, r),
+                )
+            }
+        }
+    }
+
+    pub fn contains(&self, pos: IVec3) -> bool {
+        let (min, max) = self.bounds();
+        if pos.cmplt(min).any() || pos.cmpgt(max).any() {
+            return false;
+        }
+        match *self {
+            Self::Box { .. } => true,
+            Self::Sphere { center, radius } => {
+                (pos - center).length_squared() as f32 <= radius * radius
+            }
+            Self::Cylinder { base, radius, .. } => {
+                let offset = pos - base;
+                (offset.x * offset.x + offset.z * offset.z) as f32 <= radius * radius
+            }
+        }
+    }
+
+    /// Every position inside the brush.
+    pub fn positions(&self) -> impl Iterator<Item = IVec3> + '_ {
+        let (min, max) = self.bounds();
+        (min.x..=max.x)
+            .flat_map(move |x| {
+                (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+            })
+            .filter(|pos| self.contains(*pos))
+    }
+}
+
+/// A tile which was changed by an edit.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Change {
+    pub pos: IVec3,
+    pub before: Voxel,
+    pub after: Voxel,
+}
+
+/// Everything one edit changed, which can be reverted and applied again.
+#[derive(Clone, Default, Debug)]
+pub struct ChangeSet {
+    changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Every chunk holding a changed tile.
+    pub fn chunks(&self) -> FastHashSet<IVec3> {
+        self.changes
+            .iter()
+            .map(|change| World::split_pos(change.pos).0)
+            .collect()
+    }
+
+    /// Puts every tile back the way it was before the edit. Nothing changes unless every chunk the
+    /// edit touched is loaded, so it is never half reverted.
+    pub fn revert(&self, world: &mut World, registry: &TileRegistry) -> Result<(), EditError> {
+        self.check_loaded(world)?;
+        let voxels = self.changes.iter().rev().map(|c| (c.pos, c.before));
+        world.apply_edit(voxels, registry);
+        Ok(())
+    }
+
+    /// Makes the edit again after it was reverted, as long as every chunk it touched is loaded.
+    pub fn reapply(&self, world: &mut World, registry: &TileRegistry) -> Result<(), EditError> {
+        self.check_loaded(world)?;
+        let voxels = self.changes.iter().map(|c| (c.pos, c.after));
+        world.apply_edit(voxels, registry);
+        Ok(())
+    }
+
+    fn check_loaded(&self, world: &World) -> Result<(), EditError> {
+        match self
+            .chunks()
+            .into_iter()
+            .find(|chunk| !world.is_loaded(*chunk))
+        {
+            Some(chunk) => Err(EditError::Unloaded(chunk)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A copied region of the world, which can be turned and flipped before being pasted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Clipboard {
+    size: IVec3,
+    voxels: Vec<Voxel>,
+}
+
+impl Clipboard {
+    /// Copies every tile between two corners, including both. Unloaded tiles are copied as air.
+    pub fn copy(world: &World, a: IVec3, b: IVec3) -> Self {
+        let (min, max) = (a.min(b), a.max(b));
+        let size = max - min + IVec3::ONE;
+        let mut clipboard = Self {
+            size,
+            voxels: vec![Voxel::AIR; (size.x * size.y * size.z) as usize],
+        };
+        for loc in clipboard.locations() {
+            let i = clipboard.index(loc);
+            clipboard.voxels[i] = world.voxel(min + loc);
+        }
+        clipboard
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    /// The voxel at a location in the clipboard, or air outside of it.
+    pub fn get(&self, loc: IVec3) -> Voxel {
+        match loc.cmpge(IVec3::ZERO).all() && loc.cmplt(self.size).all() {
+            true => self.voxels[self.index(loc)],
+            false => Voxel::AIR,
+        }
+    }
+
+    /// Turns the clipboard by quarter turns around the vertical axis, from +X towards +Z.
+    /// States turn along with it, see [`turn_state`].
+    pub fn rotated(&self, turns: i32, registry: &TileRegistry) -> Self {
+        let turn = |dir: IVec3| IVec3::new(-dir.z, dir.y, dir.x);
+        let mut clipboard = self.clone();
+        for _ in 0..turns.rem_euclid(4) {
+            let old = clipboard;
+            let size = IVec3::new(old.size.z, old.size.y, old.size.x);
+            clipboard = Self {
+                size,
+                voxels: vec![Voxel::AIR; old.voxels.len()],
+            };
+            for loc in old.locations() {
+                let turned = IVec3::new(old.size.z - 1 - loc.z, loc.y, loc.x);
+                let i = clipboard.index(turned);
+                clipboard.voxels[i] = turn_voxel(old.get(loc), registry, turn);
+            }
+        }
+        clipboard
+    }
+
+    /// Flips the clipboard along an axis, from 0 for x to 2 for z. States flip along with it, see
+    /// [`turn_state`].
+    pub fn mirrored(&self, axis: usize, registry: &TileRegistry) -> Self {
+        let flip = |mut dir: IVec3| {
+            dir[axis] = -dir[axis];
+            dir
+        };
+        let mut clipboard = self.clone();
+        for loc in self.locations() {
+            let mut flipped = loc;
+            flipped[axis] = self.size[axis] - 1 - loc[axis];
+            let i = clipboard.index(flipped);
+            clipboard.voxels[i] = turn_voxel(self.get(loc), registry, flip);
+        }
+        clipboard
+    }
+
+    /// Every voxel with the world position it lands on when pasted with its first corner at `pos`.
+    pub fn voxels_at(&self, pos: IVec3) -> impl Iterator<Item = (IVec3, Voxel)> + '_ {
+        self.locations().map(move |loc| (pos + loc, self.get(loc)))
+    }
+
+    fn locations(&self) -> impl Iterator<Item = IVec3> {
+        let size = self.size;
+        (0..size.x).flat_map(move |x| {
+            (0..size.y).flat_map(move |y| (0..size.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    fn index(&self, loc: IVec3) -> usize {
+        (loc.x + loc.y * self.size.x + loc.z * self.size.x * self.size.y) as usize
+    }
+}
+
+/// Moves a state along with its tile when it is turned or flipped, where `turn` maps each direction
+/// to where it ends up. Values naming a face, like a `facing` or a slab's `half`, and values naming an
+/// axis follow their direction, while properties named after a face, like a fence's connections, swap
+/// values with the face they end up on. Other properties are kept.
+pub fn turn_state(def: &TileDef, state: TileState, turn: impl Fn(IVec3) -> IVec3) -> TileState {
+    let turn_face = |name: &str| {
+        let face = FACE_NAMES.iter().position(|f| *f == name)?;
+        let dir = turn(IVec3::from(FACE_OFFSETS[face]));
+        let face = FACE_OFFSETS.iter().position(|f| IVec3::from(*f) == dir)?;
+        Some(FACE_NAMES[face])
+    };
+    let turn_axis = |name: &str| {
+        let axis = AXIS_NAMES.iter().position(|a| *a == name)?;
+        let dir = turn(IVec3::AXES[axis]).abs();
+        Some(AXIS_NAMES[IVec3::AXES.iter().position(|a| *a == dir)?])
+    };
+
+    let mut turned = state;
+    for property in &def.properties {
+        let Some(value) = def.property(state, &property.name) else {
+            continue;
+        };
+        let (name, value) = match turn_face(&property.name) {
+            Some(name) => (name, value),
+            None => match turn_face(value).or_else(|| turn_axis(value)) {
+                Some(value) => (property.name.as_str(), value),
+                None => continue,
+            },
+        };
+        turned = def.with_property(turned, name, value).unwrap_or(turned);
+    }
+    turned
+}
+
+fn turn_voxel(voxel: Voxel, registry: &TileRegistry, turn: impl Fn(IVec3) -> IVec3) -> Voxel {
+    match voxel.tile {
+        Some(tile) => Voxel::new(
+            voxel.tile,
+            turn_state(registry.get(tile), voxel.state, turn),
+        ),
+        None => voxel,
+    }
+}
+
+impl World {
+    pub fn voxel(&self, pos: IVec3) -> Voxel {
+        Voxel::new(self.get_tile(pos), self.get_state(pos))
+    }
+
+    /// Sets many tiles at once, returning what changed so it can be undone.
+    ///
+    /// Tiles in unloaded chunks are skipped. Light is updated once for the whole edit, and each
+    /// chunk it touches is only marked dirty, so the streamer remeshes it once however many of its
+    /// tiles changed.
+    pub fn apply_edit(
+        &mut self,
+        voxels: impl IntoIterator<Item = (IVec3, Voxel)>,
+        registry: &TileRegistry,
+    ) -> ChangeSet {
+        let mut changes = ChangeSet::default();
+        for (pos, voxel) in voxels {
+            if !self.is_loaded(Self::split_pos(pos).0) {
+                continue;
+            }
+            let before = self.voxel(pos);
+            if before == voxel {
+                continue;
+            }
+
+            self.set_tile(pos, voxel.tile);
+            self.set_state(pos, voxel.state);
+            changes.changes.push(Change {
+                pos,
+                before,
+                after: voxel,
+            });
+        }
+
+        self.update_lights(changes.changes.iter().map(|c| c.pos), registry);
+        changes
+    }
+
+    /// Sets every tile inside a brush.
+    pub fn fill(&mut self, brush: &Brush, voxel: Voxel, registry: &TileRegistry) -> ChangeSet {
+        self.apply_edit(brush.positions().map(|pos| (pos, voxel)), registry)
+    }
+
+    /// Sets every tile inside a brush which is currently `from`, whatever its state.
+    pub fn replace(
+        &mut self,
+        brush: &Brush,
+        from: Option<TileId>,
+        to: Voxel,
+        registry: &TileRegistry,
+    ) -> ChangeSet {
+        let positions = brush
+            .positions()
+            .filter(|pos| self.get_tile(*pos) == from)
+            .collect::<Vec<_>>();
+        self.apply_edit(positions.into_iter().map(|pos| (pos, to)), registry)
+    }
+
+    /// Pastes a clipboard with its first corner at `pos`. With `skip_air`, the air in the clipboard
+    /// leaves the world alone instead of clearing it.
+    pub fn paste(
+        &mut self,
+        clipboard: &Clipboard,
+        pos: IVec3,
+        skip_air: bool,
+        registry: &TileRegistry,
+    ) -> ChangeSet {
+        let voxels = clipboard
+            .voxels_at(pos)
+            .filter(|(_, voxel)| !skip_air || voxel.tile.is_some());
+        self.apply_edit(voxels, registry)
+    }
+}
+
+/// The edits made to a world, which can be undone and redone in order.
+pub struct EditHistory {
+    /// The most edits remembered. The oldest are forgotten past it.
+    pub limit: usize,
+
+    undo: Vec<ChangeSet>,
+    redo: Vec<ChangeSet>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        Self {
+            limit: 256,
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+
+    /// Remembers an edit which was just made, forgetting anything that was undone before it.
+    /// Edits which changed nothing are ignored.
+    pub fn record(&mut self, changes: ChangeSet) {
+        if changes.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push(changes);
+        if self.undo.len() > self.limit {
+            let excess = self.undo.len() - self.limit;
+            self.undo.drain(..excess);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Reverts the latest edit, returning it, or `None` if there is nothing to undo.
+    /// An edit reaching into unloaded chunks stays where it is, so it can be undone once they load.
+    pub fn undo(
+        &mut self,
+        world: &mut World,
+        registry: &TileRegistry,
+    ) -> Result<Option<&ChangeSet>, EditError> {
+        let Some(changes) = self.undo.last() else {
+            return Ok(None);
+        };
+        changes.revert(world, registry)?;
+        let changes = self.undo.pop().unwrap();
+        self.redo.push(changes);
+        Ok(self.redo.last())
+    }
+
+    /// Makes the latest undone edit again, returning it, or `None` if there is nothing to redo.
+    /// Like [`Self::undo`], it waits for every chunk the edit touched to be loaded.
+    pub fn redo(
+        &mut self,
+        world: &mut World,
+        registry: &TileRegistry,
+    ) -> Result<Option<&ChangeSet>, EditError> {
+        let Some(changes) = self.redo.last() else {
+            return Ok(None);
+        };
+        changes.reapply(world, registry)?;
+        let changes = self.redo.pop().unwrap();
+        self.undo.push(changes);
+        Ok(self.undo.last())
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    const TILES: &str = r#"
+[[tile]]
+name = "stone"
+
+[[tile]]
+name = "log"
+properties = [{ name = "axis", values = ["y", "x", "z"] }]
+
+[[tile]]
+name = "stairs"
+properties = [{ name = "facing", values = ["back", "left", "front", "right"] }]
+
+[[tile]]
+name = "slab"
+properties = [{ name = "half", values = ["bottom", "top"] }]
+
+[[tile]]
+name = "fence"
+properties = [
+    { name = "left", values = ["false", "true"] },
+    { name = "right", values = ["false", "true"] },
+    { name = "front", values = ["false", "true"] },
+    { name = "back", values = ["false", "true"] },
+]
+"#;
+
+    fn setup(chunks: &[IVec3]) -> (World, TileRegistry) {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let mut world = World::new(0);
+        for chunk in chunks {
+            world.insert_chunk(*chunk, Chunk::new());
+        }
+        (world, registry)
+    }
+
+    fn voxel(registry: &TileRegistry, tile: &str, properties: &[(&str, &str)]) -> Voxel {
+        let id = registry.id(tile).unwrap();
+        let def = registry.get(id);
+        let state = properties
+            .iter()
+            .fold(TileState::default(), |state, (name, value)| {
+                def.with_property(state, name, value).unwrap()
+            });
+        Voxel::new(Some(id), state)
+    }
+
+    #[test]
+    fn fill_undo_redo() {
+        let (mut world, registry) = setup(&[IVec3::ZERO, IVec3::X]);
+        let stone = voxel(&registry, "stone", &[]);
+        let brush = Brush::Box {
+            min: IVec3::new(30, 0, 0),
+            max: IVec3::new(33, 2, 2),
+        };
+
+        let mut history = EditHistory::new();
+        history.record(world.fill(&brush, stone, &registry));
+        assert!(brush.positions().all(|pos| world.voxel(pos) == stone));
+
+        let undone = history.undo(&mut world, &registry).unwrap().unwrap();
+        assert_eq!(undone.len(), 4 * 3 * 3);
+        assert_eq!(undone.chunks().len(), 2);
+        assert!(brush.positions().all(|pos| world.voxel(pos) == Voxel::AIR));
+        assert!(!history.can_undo());
+
+        history.redo(&mut world, &registry).unwrap().unwrap();
+        assert!(brush.positions().all(|pos| world.voxel(pos) == stone));
+        assert!(!history.can_redo());
+        assert!(history.redo(&mut world, &registry).unwrap().is_none());
+    }
+
+    #[test]
+    fn undo_waits_for_unloaded_chunks() {
+        let (mut world, registry) = setup(&[IVec3::ZERO, IVec3::X]);
+        let stone = voxel(&registry, "stone", &[]);
+        let brush = Brush::Box {
+            min: IVec3::new(31, 0, 0),
+            max: IVec3::new(32, 0, 0),
+        };
+
+        let mut history = EditHistory::new();
+        history.record(world.fill(&brush, stone, &registry));
+        let chunk = world.remove_chunk(IVec3::X).unwrap();
+
+        assert_eq!(
+            history.undo(&mut world, &registry).map(|c| c.is_some()),
+            Err(EditError::Unloaded(IVec3::X))
+        );
+        assert_eq!(world.voxel(IVec3::new(31, 0, 0)), stone);
+        assert!(history.can_undo());
+
+        world.insert_chunk(IVec3::X, chunk);
+        history.undo(&mut world, &registry).unwrap().unwrap();
+        assert!(brush.positions().all(|pos| world.voxel(pos) == Voxel::AIR));
+    }
+
+    fn build(registry: &TileRegistry) -> Clipboard {
+        let (mut world, _) = setup(&[IVec3::ZERO]);
+        let voxels = [
+            voxel(registry, "stone", &[]),
+            voxel(registry, "log", &[("axis", "x")]),
+            voxel(registry, "stairs", &[("facing", "back")]),
+            voxel(registry, "slab", &[("half", "top")]),
+            voxel(registry, "fence", &[("left", "true"), ("back", "true")]),
+        ];
+        for (i, voxel) in voxels.into_iter().enumerate() {
+            world.apply_edit([(IVec3::new(i as i32, i as i32 % 2, 0), voxel)], registry);
+        }
+        Clipboard::copy(&world, IVec3::ZERO, IVec3::new(4, 1, 2))
+    }
+
+    #[test]
+    fn rotating_and_mirroring_round_trips() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let clipboard = build(&registry);
+
+        let mut rotated = clipboard.clone();
+        for turn in 1..=4 {
+            rotated = rotated.rotated(1, &registry);
+            assert_eq!(rotated == clipboard, turn == 4);
+        }
+        assert_eq!(clipboard.rotated(4, &registry), clipboard);
+        assert_eq!(
+            clipboard.rotated(-1, &registry),
+            clipboard.rotated(3, &registry)
+        );
+
+        for axis in 0..3 {
+            let mirrored = clipboard.mirrored(axis, &registry);
+            assert_ne!(mirrored, clipboard);
+            assert_eq!(mirrored.mirrored(axis, &registry), clipboard);
+        }
+    }
+
+    #[test]
+    fn states_turn_with_the_clipboard() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let clipboard = build(&registry);
+
+        // A quarter turn takes +X to +Z, moving the tile at x = i to z = i.
+        let rotated = clipboard.rotated(1, &registry);
+        let at = |i: i32| rotated.get(IVec3::new(2, i % 2, i));
+        assert_eq!(at(1), voxel(&registry, "log", &[("axis", "z")]));
+        assert_eq!(at(2), voxel(&registry, "stairs", &[("facing", "left")]));
+        assert_eq!(at(3), voxel(&registry, "slab", &[("half", "top")]));
+        assert_eq!(
+            at(4),
+            voxel(&registry, "fence", &[("front", "true"), ("left", "true")])
+        );
+
+        let mirrored = clipboard.mirrored(2, &registry);
+        let at = |i: i32| mirrored.get(IVec3::new(i, i % 2, 2));
+        assert_eq!(at(1), voxel(&registry, "log", &[("axis", "x")]));
+        assert_eq!(at(2), voxel(&registry, "stairs", &[("facing", "front")]));
+        assert_eq!(
+            at(4),
+            voxel(&registry, "fence", &[("left", "true"), ("front", "true")])
+        );
+
+        let flipped = clipboard.mirrored(1, &registry);
+        assert_eq!(
+            flipped.get(IVec3::new(3, 0, 0)),
+            voxel(&registry, "slab", &[("half", "bottom")])
+        );
+    }
+}
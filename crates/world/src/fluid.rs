@@ -0,0 +1,337 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use glam::IVec3;
+use wgpu::naga::FastHashSet;
+
+use render::mesh::builder::MeshBuilder;
+
+use crate::{
+    chunk::{Chunk, ChunkNeighbors, CHUNK_SIZE, FACE_OFFSETS},
+    light,
+    mesher::vertex_ao,
+    tile::{TileId, TileRegistry},
+    world::World,
+};
+
+/// The level of a fluid source, which never drains. Flowing fluid ranges from 1 up to one below this.
+pub const FLUID_SOURCE: u8 = 8;
+
+/// The level of fluid falling from above, which is as full as flowing fluid gets.
+const FALLING: u8 = FLUID_SOURCE - 1;
+
+/// Turns the value stored by [`Chunk::fluid`] into a level, from 1 to [`FLUID_SOURCE`].
+/// Sources are stored as 0, so fluids placed by editing or generation start out as sources.
+pub fn fluid_level(stored: u8) -> u8 {
+    match stored {
+        0 => FLUID_SOURCE,
+        level => level,
+    }
+}
+
+/// How tall fluid of a level is drawn, leaving a gap under a full tile so pools read as fluid.
+pub fn fluid_height(level: u8) -> f32 {
+    level as f32 / FLUID_SOURCE as f32 * 0.875
+}
+
+impl World {
+    /// The level of the fluid at a position, or `None` if it isn't loaded.
+    /// Tiles which aren't fluids have the level of a source.
+    pub fn fluid_level(&self, pos: IVec3) -> Option<u8> {
+        let (chunk, loc) = Self::split_pos(pos);
+        Some(fluid_level(self.chunk(chunk)?.fluid(loc)))
+    }
+
+    /// Sets the level of the fluid at a position, marking the chunks that draw it dirty.
+    pub fn set_fluid_level(&mut self, pos: IVec3, level: u8) {
+        let (chunk, loc) = Self::split_pos(pos);
+        let Some(chunk) = self.chunk_mut(chunk) else {
+            return;
+        };
+        chunk.set_fluid(loc, level % FLUID_SOURCE);
+        self.mark_dirty(pos);
+    }
+}
+
+/// Flows fluids through the world as a cellular automaton, a bounded number of tiles per tick.
+///
+/// Flowing fluid takes its level from its neighbors every step: full below any fluid, otherwise
+/// [`crate::tile::FluidDef::drop`] less than its fullest neighbor, and drains away once that
+/// reaches nothing, so removing a source drains everything fed by it. Fluid falls down whenever it
+/// can, and only spreads sideways when it lands on something.
+///
+/// Only tiles which were scheduled are looked at, so call [`Self::tile_changed`] after editing the
+/// world.
+pub struct FluidSim {
+    /// The most tiles updated in one tick. Anything past it waits for the next tick.
+    pub max_updates: usize,
+    /// How many ticks run per second.
+    pub tick_rate: f32,
+
+    tick: u64,
+    elapsed: f32,
+    scheduled: BinaryHeap<Reverse<(u64, [i32; 3])>>,
+    queued: FastHashSet<IVec3>,
+}
+
+impl Default for FluidSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FluidSim {
+    pub fn new() -> Self {
+        Self {
+            max_updates: 2048,
+            tick_rate: 20.0,
+
+            tick: 0,
+            elapsed: 0.0,
+            scheduled: BinaryHeap::new(),
+            queued: FastHashSet::default(),
+        }
+    }
+
+    /// The number of tiles waiting to be updated.
+    pub fn pending(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Updates a tile after `delay` ticks, unless it is already waiting.
+    pub fn schedule(&mut self, pos: IVec3, delay: u32) {
+        if self.queued.insert(pos) {
+            self.scheduled
+                .push(Reverse((self.tick + delay as u64, pos.to_array())));
+        }
+    }
+
+    /// Wakes up any fluid at or next to a tile which was edited.
+    pub fn tile_changed(&mut self, world: &World, registry: &TileRegistry, pos: IVec3) {
+        for offset in [[0; 3]].into_iter().chain(FACE_OFFSETS) {
+            let pos = pos + IVec3::from(offset);
+            if let Some(interval) = fluid_interval(world, registry, pos) {
+                self.schedule(pos, interval);
+            }
+        }
+    }
+
+    /// Runs as many ticks as fit in `delta` seconds.
+    pub fn update(&mut self, world: &mut World, registry: &TileRegistry, delta: f32) {
+        self.elapsed += delta;
+        let tick_length = 1.0 / self.tick_rate;
+        // Don't try to catch up on more than a few ticks after a long frame.
+        self.elapsed = self.elapsed.min(tick_length * 4.0);
+        while self.elapsed >= tick_length {
+            self.elapsed -= tick_length;
+            self.tick(world, registry);
+        }
+    }
+
+    /// Updates every tile that is due, up to [`Self::max_updates`].
+    pub fn tick(&mut self, world: &mut World, registry: &TileRegistry) {
+        self.tick += 1;
+        for _ in 0..self.max_updates {
+            match self.scheduled.peek() {
+                Some(Reverse((due, _))) if *due <= self.tick => {}
+                _ => break,
+            }
+            let Reverse((_, pos)) = self.scheduled.pop().unwrap();
+            let pos = IVec3::from(pos);
+            self.queued.remove(&pos);
+            self.flow(world, registry, pos);
+        }
+    }
+
+    fn flow(&mut self, world: &mut World, registry: &TileRegistry, pos: IVec3) {
+        let Some(tile) = world.get_tile(pos) else {
+            return;
+        };
+        let Some(def) = registry.get(tile).fluid.clone() else {
+            return;
+        };
+        let Some(mut level) = world.fluid_level(pos) else {
+            return;
+        };
+
+        if level != FLUID_SOURCE {
+            let above = world.get_tile(pos + IVec3::Y) == Some(tile);
+            let target = match above {
+                true => FALLING,
+                false => FACE_OFFSETS[2..]
+                    .iter()
+                    .map(|offset| pos + IVec3::from(*offset))
+                    .filter(|side| world.get_tile(*side) == Some(tile))
+                    .filter_map(|side| world.fluid_level(side))
+                    .max()
+                    .map_or(0, |side| side.saturating_sub(def.drop)),
+            };
+
+            if target != level {
+                self.wake_neighbors(world, registry, pos, def.tick_interval);
+                if target == 0 {
+                    world.set_loaded_tile(pos, None);
+                    world.update_light(pos, registry);
+                    return;
+                }
+                world.set_fluid_level(pos, target);
+                level = target;
+            }
+        }
+
+        // Fall first, and only spread sideways once there is something to land on.
+        let below = pos - IVec3::Y;
+        if self.fill(world, registry, below, tile, FALLING) {
+            self.schedule(below, def.tick_interval);
+            return;
+        }
+        match world.get_tile(below) {
+            // Below is unloaded, so wait for it instead of spreading over the gap.
+            None => return,
+            // Fluid landing in more of itself just merges into it.
+            Some(below) if below == tile => return,
+            Some(_) => {}
+        }
+
+        let spread = level.saturating_sub(def.drop);
+        if spread == 0 {
+            return;
+        }
+        for offset in &FACE_OFFSETS[2..] {
+            let side = pos + IVec3::from(*offset);
+            if self.fill(world, registry, side, tile, spread) {
+                self.schedule(side, def.tick_interval);
+            }
+        }
+    }
+
+    /// Fills a tile with fluid if it is open, or holds less of the same fluid, returning whether it
+    /// changed. Tiles which aren't solid, like flowers, are washed away.
+    fn fill(
+        &mut self,
+        world: &mut World,
+        registry: &TileRegistry,
+        pos: IVec3,
+        fluid: TileId,
+        level: u8,
+    ) -> bool {
+        match world.get_tile(pos) {
+            Some(tile) if tile == fluid => {
+                let current = world.fluid_level(pos).unwrap();
+                if current >= level {
+                    return false;
+                }
+                world.set_fluid_level(pos, level);
+            }
+            Some(tile) if registry.get(tile).solid || registry.get(tile).fluid.is_some() => {
+                return false;
+            }
+            _ => {
+                if !world.set_loaded_tile(pos, Some(fluid)) {
+                    return false;
+                }
+                world.set_fluid_level(pos, level);
+                world.update_light(pos, registry);
+            }
+        }
+        true
+    }
+
+    fn wake_neighbors(&mut self, world: &World, registry: &TileRegistry, pos: IVec3, delay: u32) {
+        for offset in FACE_OFFSETS {
+            let pos = pos + IVec3::from(offset);
+            if fluid_interval(world, registry, pos).is_some() {
+                self.schedule(pos, delay);
+            }
+        }
+    }
+}
+
+/// The tick interval of the fluid at a position, if there is one.
+fn fluid_interval(world: &World, registry: &TileRegistry, pos: IVec3) -> Option<u32> {
+    let tile = world.get_tile(pos)?;
+    Some(registry.get(tile).fluid.as_ref()?.tick_interval.max(1))
+}
+
+/// Meshes the fluid tiles of a chunk, which the regular meshers skip.
+/// Fluid is drawn as tall as its level, except under more of the same fluid, so the top face shows
+/// even when a tile sits right above it. Sides against lower fluid of the same kind are drawn down
+/// to the neighbor's surface, so flows step down without gaps.
+pub(crate) fn mesh(
+    chunk: &Chunk,
+    registry: &TileRegistry,
+    neighbors: &ChunkNeighbors,
+    builder: &mut MeshBuilder,
+    offset: [f32; 3],
+) {
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let loc = [x, y, z];
+                let Some(tile) = chunk.get(loc) else {
+                    continue;
+                };
+                if registry.get(tile).fluid.is_none() {
+                    continue;
+                }
+
+                let pos = loc.map(|v| v as i32);
+                let Some(height) = surface_height(chunk, neighbors, tile, pos) else {
+                    continue;
+                };
+
+                for (face, step) in FACE_OFFSETS.iter().enumerate() {
+                    let side = [0, 1, 2].map(|i| pos[i] + step[i]);
+                    let bottom = match face {
+                        0 if height == 1.0 => continue,
+                        0 => height,
+                        // Only the part above a lower neighbor of the same fluid shows.
+                        2.. => match surface_height(chunk, neighbors, tile, side) {
+                            Some(side) if side >= height => continue,
+                            Some(side) => side,
+                            None => 0.0,
+                        },
+                        _ => 0.0,
+                    };
+                    if bottom == 0.0 && !chunk.face_visible(registry, neighbors, tile, loc, face) {
+                        continue;
+                    }
+
+                    builder.add_box_face(
+                        [0, 1, 2].map(|i| offset[i] + loc[i] as f32),
+                        [0.0, bottom, 0.0],
+                        [1.0, height, 1.0],
+                        face,
+                        registry.texture_layer(tile, chunk.state(loc), face),
+                        light::vertex_light(chunk.face_light(neighbors, loc, face)),
+                        vertex_ao([3; 4]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// How tall the fluid `tile` at a location, which may lie in a neighboring chunk, is drawn, or
+/// `None` if it holds something else. Fluid under more of itself fills its whole tile.
+fn surface_height(
+    chunk: &Chunk,
+    neighbors: &ChunkNeighbors,
+    tile: TileId,
+    pos: [i32; 3],
+) -> Option<f32> {
+    if chunk.tile_at(neighbors, pos) != Some(tile) {
+        return None;
+    }
+    if chunk.tile_at(neighbors, [pos[0], pos[1] + 1, pos[2]]) == Some(tile) {
+        return Some(1.0);
+    }
+
+    let size = CHUNK_SIZE as i32;
+    let offset = pos.map(|v| v.div_euclid(size));
+    let loc = pos.map(|v| v.rem_euclid(size) as usize);
+    let stored = match offset {
+        [0, 0, 0] => chunk.fluid(loc),
+        _ => neighbors.at(offset)?.fluid(loc),
+    };
+    Some(fluid_height(fluid_level(stored)))
+}
@@ -0,0 +1,321 @@
+use std::{fs, path::Path, sync::Arc};
+
+use glam::IVec3;
+use noise::{NoiseFn, Perlin};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    tile::{TileId, TileRegistry},
+};
+
+use super::{noise_seed, GenContext, GenStage, TerrainShape};
+
+const TEMPERATURE_SALT: u64 = 40;
+const HUMIDITY_SALT: u64 = 41;
+const DETAIL_SALT: u64 = 42;
+const DECORATION_SALT: u64 = 43;
+
+#[derive(Error, Debug)]
+pub enum BiomeError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
+    #[error("Biome `{biome}` uses tile `{tile}`, which is not registered")]
+    UnknownTile { biome: String, tile: String },
+
+    #[error("At least one biome has to be defined")]
+    NoBiomes,
+}
+
+#[derive(Deserialize)]
+struct BiomeFile {
+    #[serde(default)]
+    biome: Vec<BiomeDef>,
+}
+
+/// A biome as written in a biome toml file, with tiles referenced by name.
+#[derive(Deserialize, Clone, Debug)]
+pub struct BiomeDef {
+    pub name: String,
+
+    /// Where the biome sits in the climate, both from -1 to 1.
+    pub temperature: f64,
+    pub humidity: f64,
+
+    pub base_height: f64,
+    pub amplitude: f64,
+
+    pub surface: String,
+    pub subsurface: String,
+    #[serde(default = "default_subsurface_depth")]
+    pub subsurface_depth: usize,
+
+    #[serde(default)]
+    pub decorations: Vec<DecorationDef>,
+}
+
+fn default_subsurface_depth() -> usize {
+    3
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DecorationDef {
+    pub tile: String,
+    /// The chance of placing the tile on each surface tile.
+    pub chance: f64,
+}
+
+/// A biome with its tiles resolved against a [`TileRegistry`].
+#[derive(Clone, Debug)]
+pub struct Biome {
+    pub name: String,
+
+    pub temperature: f64,
+    pub humidity: f64,
+
+    pub base_height: f64,
+    pub amplitude: f64,
+
+    pub surface: TileId,
+    pub subsurface: TileId,
+    pub subsurface_depth: usize,
+
+    pub decorations: Vec<(TileId, f64)>,
+}
+
+impl Biome {
+    fn resolve(def: BiomeDef, registry: &TileRegistry) -> Result<Self, BiomeError> {
+        let tile = |name: &str| {
+            registry.id(name).ok_or_else(|| BiomeError::UnknownTile {
+                biome: def.name.clone(),
+                tile: name.to_string(),
+            })
+        };
+
+        let decorations = def
+            .decorations
+            .iter()
+            .map(|d| Ok((tile(&d.tile)?, d.chance)))
+            .collect::<Result<_, BiomeError>>()?;
+
+        Ok(Self {
+            surface: tile(&def.surface)?,
+            subsurface: tile(&def.subsurface)?,
+            subsurface_depth: def.subsurface_depth,
+            decorations,
+
+            temperature: def.temperature,
+            humidity: def.humidity,
+            base_height: def.base_height,
+            amplitude: def.amplitude,
+            name: def.name,
+        })
+    }
+}
+
+/// Picks biomes from two climate noise fields, temperature and humidity.
+/// Each biome owns the point in the climate closest to it, and terrain height is blended between
+/// nearby biomes, so borders slope smoothly instead of forming cliffs.
+pub struct BiomeMap {
+    biomes: Vec<Biome>,
+
+    temperature: Perlin,
+    humidity: Perlin,
+    detail: Perlin,
+
+    /// How quickly the climate changes across the world.
+    pub climate_frequency: f64,
+    /// How quickly the terrain height changes inside a biome.
+    pub detail_frequency: f64,
+    /// How far apart in the climate biomes still blend together. Larger values give wider borders.
+    pub blend: f64,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u64, biomes: Vec<Biome>) -> Result<Self, BiomeError> {
+        if biomes.is_empty() {
+            return Err(BiomeError::NoBiomes);
+        }
+
+        Ok(Self {
+            biomes,
+            temperature: Perlin::new(noise_seed(seed, TEMPERATURE_SALT)),
+            humidity: Perlin::new(noise_seed(seed, HUMIDITY_SALT)),
+            detail: Perlin::new(noise_seed(seed, DETAIL_SALT)),
+            climate_frequency: 0.004,
+            detail_frequency: 0.02,
+            blend: 0.15,
+        })
+    }
+
+    pub fn from_toml(seed: u64, src: &str, registry: &TileRegistry) -> Result<Self, BiomeError> {
+        let file: BiomeFile = toml::from_str(src)?;
+        let biomes = file
+            .biome
+            .into_iter()
+            .map(|def| Biome::resolve(def, registry))
+            .collect::<Result<_, _>>()?;
+        Self::new(seed, biomes)
+    }
+
+    pub fn load(
+        seed: u64,
+        path: impl AsRef<Path>,
+        registry: &TileRegistry,
+    ) -> Result<Self, BiomeError> {
+        Self::from_toml(seed, &fs::read_to_string(path)?, registry)
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// The temperature and humidity of a column.
+    pub fn climate(&self, x: i32, z: i32) -> (f64, f64) {
+        let pos = [
+            x as f64 * self.climate_frequency,
+            z as f64 * self.climate_frequency,
+        ];
+        (self.temperature.get(pos), self.humidity.get(pos))
+    }
+
+    /// The biome whose climate is closest to the column's.
+    pub fn biome_at(&self, x: i32, z: i32) -> &Biome {
+        let (temperature, humidity) = self.climate(x, z);
+        self.biomes
+            .iter()
+            .min_by(|a, b| {
+                let a = climate_distance(a, temperature, humidity);
+                let b = climate_distance(b, temperature, humidity);
+                a.total_cmp(&b)
+            })
+            .unwrap()
+    }
+
+    /// The terrain height of a column, blended between the biomes near its climate.
+    pub fn height(&self, x: i32, z: i32) -> f64 {
+        let (temperature, humidity) = self.climate(x, z);
+
+        // Gaussian weights fall off smoothly, so the height never jumps at a border.
+        let mut total = 0.0;
+        let mut base_height = 0.0;
+        let mut amplitude = 0.0;
+        for biome in &self.biomes {
+            let distance = climate_distance(biome, temperature, humidity);
+            let weight = (-distance / (self.blend * self.blend))
+                .exp()
+                .max(f64::MIN_POSITIVE);
+            total += weight;
+            base_height += biome.base_height * weight;
+            amplitude += biome.amplitude * weight;
+        }
+
+        let detail = self.detail.get([
+            x as f64 * self.detail_frequency,
+            z as f64 * self.detail_frequency,
+        ]);
+        (base_height + amplitude * detail) / total
+    }
+}
+
+/// The squared distance between a biome and a point in the climate.
+fn climate_distance(biome: &Biome, temperature: f64, humidity: f64) -> f64 {
+    (biome.temperature - temperature).powi(2) + (biome.humidity - humidity).powi(2)
+}
+
+/// Terrain shaped by the blended height of each biome.
+pub struct BiomeTerrain {
+    biomes: Arc<BiomeMap>,
+}
+
+impl BiomeTerrain {
+    pub fn new(biomes: Arc<BiomeMap>) -> Self {
+        Self { biomes }
+    }
+}
+
+impl TerrainShape for BiomeTerrain {
+    fn solid(&self, pos: IVec3) -> bool {
+        (pos.y as f64) < self.biomes.height(pos.x, pos.z)
+    }
+}
+
+/// Covers exposed terrain with each biome's surface and subsurface tiles.
+pub struct BiomeSurface {
+    /// The tile that is covered, usually the terrain's fill tile.
+    pub replaces: TileId,
+}
+
+impl GenStage for BiomeSurface {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let Some(biomes) = ctx.biomes else {
+            log::warn!("BiomeSurface was used by a generator without biomes");
+            return;
+        };
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = ctx.world_pos([x, 0, z]);
+                let biome = biomes.biome_at(column.x, column.z);
+
+                for y in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]) != Some(self.replaces) {
+                        continue;
+                    }
+
+                    let Some(depth) = (1..=biome.subsurface_depth + 1)
+                        .find(|k| ctx.air_above_by(chunk, [x, y, z], *k))
+                    else {
+                        continue;
+                    };
+                    let tile = match depth {
+                        1 => biome.surface,
+                        _ => biome.subsurface,
+                    };
+                    chunk.set([x, y, z], Some(tile));
+                }
+            }
+        }
+    }
+}
+
+/// Places each biome's decorations on top of its surface tiles, at the biome's density.
+pub struct BiomeDecorations;
+
+impl GenStage for BiomeDecorations {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let Some(biomes) = ctx.biomes else {
+            log::warn!("BiomeDecorations was used by a generator without biomes");
+            return;
+        };
+
+        let mut rng = ctx.rng(DECORATION_SALT);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = ctx.world_pos([x, 0, z]);
+                let biome = biomes.biome_at(column.x, column.z);
+
+                for y in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]) != Some(biome.surface)
+                        || !ctx.air_above_by(chunk, [x, y, z], 1)
+                    {
+                        continue;
+                    }
+
+                    let above = ctx.world_pos([x, y, z]) + IVec3::Y;
+                    for (tile, chance) in &biome.decorations {
+                        if rng.chance(*chance) {
+                            ctx.place(chunk, above, *tile);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use noise::{NoiseFn, Perlin};
+
+use crate::chunk::{Chunk, CHUNK_SIZE};
+
+use super::{noise_seed, GenContext, GenStage};
+
+const FIRST_SALT: u64 = 10;
+const SECOND_SALT: u64 = 11;
+
+/// Carves winding tunnels where two noise fields both cross zero.
+/// Should run after [`super::surface::SurfaceLayers`] so cave ceilings aren't covered in grass.
+pub struct Caves {
+    first: Perlin,
+    second: Perlin,
+    pub frequency: f64,
+    /// How close to zero both noise fields must be, larger values make wider tunnels.
+    pub radius: f64,
+}
+
+impl Caves {
+    pub fn new(seed: u64, frequency: f64, radius: f64) -> Self {
+        Self {
+            first: Perlin::new(noise_seed(seed, FIRST_SALT)),
+            second: Perlin::new(noise_seed(seed, SECOND_SALT)),
+            frequency,
+            radius,
+        }
+    }
+}
+
+impl GenStage for Caves {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]).is_none() {
+                        continue;
+                    }
+
+                    let pos = (ctx.world_pos([x, y, z]).as_dvec3() * self.frequency).to_array();
+                    if self.first.get(pos).abs() < self.radius
+                        && self.second.get(pos).abs() < self.radius
+                    {
+                        chunk.set([x, y, z], None);
+                    }
+                }
+            }
+        }
+    }
+}
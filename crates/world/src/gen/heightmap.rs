@@ -0,0 +1,303 @@
+use std::path::Path;
+
+use glam::IVec3;
+use image::{DynamicImage, ImageError};
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    tile::TileId,
+};
+
+use super::{GenContext, GenStage, TerrainShape};
+
+/// What an image reads as past its edges.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum EdgeMode {
+    /// The edge pixels stretch out forever.
+    #[default]
+    Clamp,
+    /// The image repeats.
+    Tile,
+}
+
+/// A grayscale image laid over the world, one pixel per tile column, with its top left corner at
+/// the origin and its rows running along z.
+#[derive(Clone, Debug)]
+pub struct ImageMap {
+    width: u32,
+    depth: u32,
+    /// Every pixel from 0 for black to 1 for white.
+    values: Vec<f32>,
+    pub edge: EdgeMode,
+}
+
+impl ImageMap {
+    /// Reads an image as grayscale. 16 bit images keep their full precision.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let luma = image.to_luma16();
+        Self {
+            width: luma.width(),
+            depth: luma.height(),
+            values: luma
+                .pixels()
+                .map(|p| p.0[0] as f32 / u16::MAX as f32)
+                .collect(),
+            edge: EdgeMode::default(),
+        }
+    }
+
+    pub fn from_png(bytes: &[u8]) -> Result<Self, ImageError> {
+        let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)?;
+        Ok(Self::from_image(&image))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        Ok(Self::from_image(&image::open(path)?))
+    }
+
+    pub fn with_edge(mut self, edge: EdgeMode) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// The width and depth of the image, in pixels.
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.depth]
+    }
+
+    /// The value of the pixel over a tile column, from 0 to 1. Empty images read as black.
+    pub fn sample(&self, x: i32, z: i32) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let wrap = |v: i32, len: u32| match self.edge {
+            EdgeMode::Clamp => v.clamp(0, len as i32 - 1) as usize,
+            EdgeMode::Tile => v.rem_euclid(len as i32) as usize,
+        };
+        self.values[wrap(x, self.width) + wrap(z, self.depth) * self.width as usize]
+    }
+}
+
+/// Terrain painted as a heightmap, solid below the height of each pixel.
+pub struct ImageTerrain {
+    heights: ImageMap,
+    /// The height of black pixels.
+    pub base_height: i32,
+    /// How many tiles higher white pixels are than black ones.
+    pub vertical_scale: f32,
+}
+
+impl ImageTerrain {
+    pub fn new(heights: ImageMap, base_height: i32, vertical_scale: f32) -> Self {
+        Self {
+            heights,
+            base_height,
+            vertical_scale,
+        }
+    }
+
+    pub fn heights(&self) -> &ImageMap {
+        &self.heights
+    }
+
+    /// The height of the first tile above the ground in a column.
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        self.base_height + (self.heights.sample(x, z) * self.vertical_scale).round() as i32
+    }
+}
+
+impl TerrainShape for ImageTerrain {
+    fn solid(&self, pos: IVec3) -> bool {
+        pos.y < self.height(pos.x, pos.z)
+    }
+}
+
+/// Fills the air below sea level with a fluid, down to the first tile that isn't air in each column,
+/// so caves and pockets under dry land stay dry. Surface stages only cover terrain with air above
+/// it, so run this after them to cover the sea floor too.
+pub struct Sea {
+    pub tile: TileId,
+    /// The height of the first tile above the sea.
+    pub level: i32,
+}
+
+impl GenStage for Sea {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let bottom = ctx.world_pos([0; 3]).y;
+        let top = (self.level - bottom).clamp(0, CHUNK_SIZE as i32) as usize;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                // Above the chunk, fall back to the terrain shape to tell if the column is open.
+                let column = ctx.world_pos([x, 0, z]);
+                let covered = (bottom + CHUNK_SIZE as i32..self.level)
+                    .any(|y| ctx.shape_solid(IVec3::new(column.x, y, column.z)));
+                if covered {
+                    continue;
+                }
+
+                for y in (0..top).rev() {
+                    if chunk.get([x, y, z]).is_some() {
+                        break;
+                    }
+                    chunk.set([x, y, z], Some(self.tile));
+                }
+            }
+        }
+    }
+}
+
+/// A grayscale image painting where a surface goes, like sand along the shore.
+pub struct Splat {
+    pub mask: ImageMap,
+    pub top: TileId,
+    pub under: TileId,
+}
+
+/// Covers exposed terrain with a top tile over a few layers of another, chosen by splat maps.
+/// The splat painted brightest over a column wins, as long as it is painted over half way, and
+/// columns without one get the default tiles.
+pub struct SplatSurface {
+    pub splats: Vec<Splat>,
+    pub top: TileId,
+    pub under: TileId,
+    /// The number of `under` tiles below the top.
+    pub depth: usize,
+    /// The tile that is covered, usually the terrain's fill tile.
+    pub replaces: TileId,
+}
+
+impl SplatSurface {
+    /// The top and under tiles of a column.
+    pub fn tiles(&self, x: i32, z: i32) -> (TileId, TileId) {
+        self.splats
+            .iter()
+            .map(|splat| (splat, splat.mask.sample(x, z)))
+            .filter(|(_, weight)| *weight > 0.5)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or((self.top, self.under), |(splat, _)| {
+                (splat.top, splat.under)
+            })
+    }
+}
+
+impl GenStage for SplatSurface {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = ctx.world_pos([x, 0, z]);
+                let (top, under) = self.tiles(column.x, column.z);
+
+                for y in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]) != Some(self.replaces) {
+                        continue;
+                    }
+
+                    let Some(depth) =
+                        (1..=self.depth + 1).find(|k| ctx.air_above_by(chunk, [x, y, z], *k))
+                    else {
+                        continue;
+                    };
+                    let tile = match depth {
+                        1 => top,
+                        _ => under,
+                    };
+                    chunk.set([x, y, z], Some(tile));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::{StagedGenerator, WorldGenerator};
+
+    const STONE: TileId = TileId(0);
+    const WATER: TileId = TileId(1);
+
+    /// A 2 by 2 image with a different value in each pixel.
+    fn map(edge: EdgeMode) -> ImageMap {
+        ImageMap {
+            width: 2,
+            depth: 2,
+            values: vec![0.0, 0.25, 0.5, 1.0],
+            edge,
+        }
+    }
+
+    #[test]
+    fn clamp_stretches_edges() {
+        let map = map(EdgeMode::Clamp);
+        assert_eq!(map.sample(0, 0), 0.0);
+        assert_eq!(map.sample(1, 1), 1.0);
+        assert_eq!(map.sample(-5, 0), 0.0);
+        assert_eq!(map.sample(1, -3), 0.25);
+        assert_eq!(map.sample(7, 0), 0.25);
+        assert_eq!(map.sample(-1, 9), 0.5);
+        assert_eq!(map.sample(2, 2), 1.0);
+    }
+
+    #[test]
+    fn tile_repeats() {
+        let map = map(EdgeMode::Tile);
+        assert_eq!(map.sample(2, 0), 0.0);
+        assert_eq!(map.sample(3, 0), 0.25);
+        assert_eq!(map.sample(-1, 0), 0.25);
+        assert_eq!(map.sample(-2, -1), 0.5);
+        assert_eq!(map.sample(-1, -1), 1.0);
+        assert_eq!(map.sample(5, 7), 1.0);
+    }
+
+    #[test]
+    fn empty_images_read_as_black() {
+        let map = ImageMap {
+            width: 0,
+            depth: 0,
+            values: vec![],
+            edge: EdgeMode::Tile,
+        };
+        assert_eq!(map.sample(3, -3), 0.0);
+    }
+
+    /// Carves sealed pockets out of the bottom and top of the column at x = 5, z = 0.
+    struct Pockets;
+
+    impl GenStage for Pockets {
+        fn apply(&self, _ctx: &GenContext, chunk: &mut Chunk) {
+            for y in (2..5).chain(CHUNK_SIZE - 3..CHUNK_SIZE) {
+                chunk.set([5, y, 0], None);
+            }
+        }
+    }
+
+    #[test]
+    fn sea_only_floods_open_air() {
+        // Ground at -40 along x = 0, and at 40 everywhere else.
+        let heights = ImageMap {
+            width: 2,
+            depth: 1,
+            values: vec![0.0, 1.0],
+            edge: EdgeMode::Clamp,
+        };
+        let generator = StagedGenerator::new(0, ImageTerrain::new(heights, -40, 80.0), STONE)
+            .with_stage(Pockets)
+            .with_stage(Sea {
+                tile: WATER,
+                level: 40,
+            });
+
+        let mut chunk = Chunk::new();
+        generator.generate(IVec3::ZERO, &mut chunk);
+        assert!((0..CHUNK_SIZE).all(|y| chunk.get([0, y, 0]) == Some(WATER)));
+        // Pockets under land stay dry, even at the top of a chunk with land above it.
+        assert!((2..5).all(|y| chunk.get([5, y, 0]).is_none()));
+        assert!((CHUNK_SIZE - 3..CHUNK_SIZE).all(|y| chunk.get([5, y, 0]).is_none()));
+
+        let mut above = Chunk::new();
+        generator.generate(IVec3::Y, &mut above);
+        assert!((0..8).all(|y| above.get([0, y, 0]) == Some(WATER)));
+        assert_eq!(above.get([0, 8, 0]), None);
+        assert!((2..5).all(|y| above.get([5, y, 0]).is_none()));
+    }
+}
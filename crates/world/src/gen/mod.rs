@@ -0,0 +1,282 @@
+use std::{cell::RefCell, ops::Range, sync::Arc};
+
+use glam::IVec3;
+
+use biome::{Biome, BiomeMap};
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    tile::TileId,
+    world::World,
+};
+
+pub mod biome;
+pub mod caves;
+pub mod heightmap;
+pub mod ores;
+pub mod structures;
+pub mod surface;
+pub mod terrain;
+
+/// Fills chunks with terrain.
+/// Generators are built for a single seed, and must only depend on that seed and the chunk position,
+/// so that a world can be regenerated exactly from its seed.
+pub trait WorldGenerator: Send + Sync {
+    /// Generates a chunk, returning the tiles placed outside of it, like the parts of a tree
+    /// reaching into the next chunk. See [`World::place_pending`].
+    fn generate(&self, pos: IVec3, chunk: &mut Chunk) -> Vec<PendingTile>;
+
+    /// The biome at a world position, for generators which use biomes.
+    fn biome_at(&self, _pos: IVec3) -> Option<&Biome> {
+        None
+    }
+}
+
+/// Decides the overall shape of the terrain.
+/// Stages can sample it outside of the chunk being generated, for example to find the surface above
+/// a chunk.
+pub trait TerrainShape: Send + Sync {
+    fn solid(&self, pos: IVec3) -> bool;
+}
+
+/// A single step of a [`StagedGenerator`], such as carving caves or placing ores.
+pub trait GenStage: Send + Sync {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk);
+}
+
+/// A tile generation placed outside the chunk being generated, waiting for its own chunk.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PendingTile {
+    pub pos: IVec3,
+    pub tile: TileId,
+}
+
+/// Everything a stage knows about the chunk it is generating.
+pub struct GenContext<'a> {
+    pub seed: u64,
+    pub pos: IVec3,
+    pub shape: &'a dyn TerrainShape,
+    pub biomes: Option<&'a BiomeMap>,
+
+    pending: RefCell<Vec<PendingTile>>,
+}
+
+impl<'a> GenContext<'a> {
+    /// The world position of the chunk's first tile.
+    pub fn origin(&self) -> IVec3 {
+        World::chunk_origin(self.pos)
+    }
+
+    /// Converts a location in the chunk to a world position.
+    pub fn world_pos(&self, loc: [usize; 3]) -> IVec3 {
+        self.origin() + IVec3::new(loc[0] as i32, loc[1] as i32, loc[2] as i32)
+    }
+
+    /// Whether the terrain shape is solid at a world position, before any stage has run.
+    pub fn shape_solid(&self, pos: IVec3) -> bool {
+        self.shape.solid(pos)
+    }
+
+    /// Whether the tile `k` tiles above a location is air. Above the chunk, this falls back to the
+    /// terrain shape, so surfaces along the top of the chunk line up with the chunk above. Tiles
+    /// placed on them with [`Self::place`] land in that chunk.
+    pub fn air_above_by(&self, chunk: &Chunk, [x, y, z]: [usize; 3], k: usize) -> bool {
+        match y + k < CHUNK_SIZE {
+            true => chunk.get([x, y + k, z]).is_none(),
+            false => !self.shape_solid(self.world_pos([x, y, z]) + IVec3::Y * k as i32),
+        }
+    }
+
+    /// A random number generator unique to this chunk and `salt`.
+    pub fn rng(&self, salt: u64) -> GenRng {
+        GenRng::new(hash(self.seed, self.pos, salt))
+    }
+
+    /// Sets the tile at a world position, which may lie outside the chunk being generated.
+    /// Tiles outside of it are returned from [`WorldGenerator::generate`] for the world to place.
+    pub fn place(&self, chunk: &mut Chunk, pos: IVec3, tile: TileId) {
+        let (chunk_pos, loc) = World::split_pos(pos);
+        match chunk_pos == self.pos {
+            true => chunk.set(loc, Some(tile)),
+            false => self.pending.borrow_mut().push(PendingTile { pos, tile }),
+        }
+    }
+}
+
+/// Generates chunks by filling the solid parts of a [`TerrainShape`], then running each stage in order.
+pub struct StagedGenerator {
+    seed: u64,
+    shape: Box<dyn TerrainShape>,
+    fill: TileId,
+    biomes: Option<Arc<BiomeMap>>,
+    stages: Vec<Box<dyn GenStage>>,
+}
+
+impl StagedGenerator {
+    pub fn new(seed: u64, shape: impl TerrainShape + 'static, fill: TileId) -> Self {
+        Self {
+            seed,
+            shape: Box::new(shape),
+            fill,
+            biomes: None,
+            stages: vec![],
+        }
+    }
+
+    /// Gives the generator's stages access to biomes, and reports them through
+    /// [`WorldGenerator::biome_at`].
+    pub fn with_biomes(mut self, biomes: Arc<BiomeMap>) -> Self {
+        self.biomes = Some(biomes);
+        self
+    }
+
+    pub fn with_stage(mut self, stage: impl GenStage + 'static) -> Self {
+        self.add_stage(stage);
+        self
+    }
+
+    pub fn add_stage(&mut self, stage: impl GenStage + 'static) -> &mut Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+}
+
+impl WorldGenerator for StagedGenerator {
+    fn generate(&self, pos: IVec3, chunk: &mut Chunk) -> Vec<PendingTile> {
+        let ctx = GenContext {
+            seed: self.seed,
+            pos,
+            shape: self.shape.as_ref(),
+            biomes: self.biomes.as_deref(),
+            pending: RefCell::new(vec![]),
+        };
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    if ctx.shape_solid(ctx.world_pos([x, y, z])) {
+                        chunk.set([x, y, z], Some(self.fill));
+                    }
+                }
+            }
+        }
+
+        for stage in &self.stages {
+            stage.apply(&ctx, chunk);
+        }
+        ctx.pending.into_inner()
+    }
+
+    fn biome_at(&self, pos: IVec3) -> Option<&Biome> {
+        Some(self.biomes.as_ref()?.biome_at(pos.x, pos.z))
+    }
+}
+
+/// A seed for a noise function, unique to `salt`.
+/// Each stage uses its own salt so that they don't all follow the same noise.
+pub fn noise_seed(seed: u64, salt: u64) -> u32 {
+    hash(seed, IVec3::ZERO, salt) as u32
+}
+
+/// Mixes a seed, position and salt into a well distributed hash.
+pub fn hash(seed: u64, pos: IVec3, salt: u64) -> u64 {
+    let mut h = seed ^ salt.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    for v in [pos.x, pos.y, pos.z] {
+        h = splitmix(h ^ (v as u32 as u64));
+    }
+    h
+}
+
+fn splitmix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// A small deterministic random number generator for world generation.
+pub struct GenRng(u64);
+
+impl GenRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        splitmix(self.0)
+    }
+
+    /// A random float in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn range(&mut self, range: Range<i32>) -> i32 {
+        let len = (range.end - range.start).max(1) as u64;
+        range.start + (self.next_u64() % len) as i32
+    }
+
+    pub fn chance(&mut self, chance: f64) -> bool {
+        self.next_f64() < chance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::{
+        caves::Caves,
+        ores::{Ore, Ores},
+        surface::Scatter,
+        terrain::HeightmapTerrain,
+    };
+
+    const STONE: TileId = TileId(0);
+    const ORE: TileId = TileId(1);
+    const FLOWER: TileId = TileId(2);
+
+    fn generator(seed: u64) -> StagedGenerator {
+        StagedGenerator::new(seed, HeightmapTerrain::new(seed, 16.0, 12.0, 0.05), STONE)
+            .with_stage(Caves::new(seed, 0.08, 0.1))
+            .with_stage(Ores::new().with_ore(Ore {
+                tile: ORE,
+                replaces: STONE,
+                veins: 8,
+                size: 6,
+                height: -64..64,
+            }))
+            .with_stage(Scatter {
+                tile: FLOWER,
+                on: STONE,
+                chance: 0.3,
+            })
+    }
+
+    /// Every tile of a generated chunk, along with the tiles placed outside of it.
+    fn generate(seed: u64, pos: IVec3) -> (Vec<Option<TileId>>, Vec<PendingTile>) {
+        let mut chunk = Chunk::new();
+        let pending = generator(seed).generate(pos, &mut chunk);
+        let mut tiles = vec![];
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    tiles.push(chunk.get([x, y, z]));
+                }
+            }
+        }
+        (tiles, pending)
+    }
+
+    #[test]
+    fn same_seed_generates_the_same_chunk() {
+        for pos in [IVec3::ZERO, IVec3::new(-3, 0, 7)] {
+            assert_eq!(generate(42, pos), generate(42, pos));
+        }
+    }
+
+    #[test]
+    fn different_seeds_generate_different_chunks() {
+        assert_ne!(generate(42, IVec3::ZERO), generate(43, IVec3::ZERO));
+    }
+}
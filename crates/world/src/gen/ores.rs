@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    tile::TileId,
+};
+
+use super::{GenContext, GenStage};
+
+const ORE_SALT: u64 = 20;
+
+pub struct Ore {
+    pub tile: TileId,
+    /// The tile that veins grow through, usually stone.
+    pub replaces: TileId,
+    /// The number of veins started in each chunk.
+    pub veins: u32,
+    /// The number of steps each vein wanders for.
+    pub size: u32,
+    /// The world heights veins can start at.
+    pub height: Range<i32>,
+}
+
+/// Scatters veins of ore, each one a random walk through the tiles it replaces.
+#[derive(Default)]
+pub struct Ores {
+    ores: Vec<Ore>,
+}
+
+impl Ores {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ore(mut self, ore: Ore) -> Self {
+        self.ores.push(ore);
+        self
+    }
+}
+
+impl GenStage for Ores {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let size = CHUNK_SIZE as i32;
+
+        for (i, ore) in self.ores.iter().enumerate() {
+            let mut rng = ctx.rng(ORE_SALT + i as u64);
+            for _ in 0..ore.veins {
+                let mut loc = [rng.range(0..size), rng.range(0..size), rng.range(0..size)];
+                let start_y = ctx.origin().y + loc[1];
+                if !ore.height.contains(&start_y) {
+                    continue;
+                }
+
+                for _ in 0..ore.size {
+                    if loc.iter().all(|v| (0..size).contains(v)) {
+                        let loc = loc.map(|v| v as usize);
+                        if chunk.get(loc) == Some(ore.replaces) {
+                            chunk.set(loc, Some(ore.tile));
+                        }
+                    }
+
+                    let axis = rng.range(0..3) as usize;
+                    loc[axis] += if rng.chance(0.5) { 1 } else { -1 };
+                }
+            }
+        }
+    }
+}
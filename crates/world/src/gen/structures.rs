@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use glam::IVec3;
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    structure::Structure,
+    tile::TileId,
+};
+
+use super::{GenContext, GenStage};
+
+const STRUCTURE_SALT: u64 = 60;
+
+pub struct StructurePlacement {
+    pub structure: Arc<Structure>,
+    /// The surface tiles the structure can stand on.
+    pub on: Vec<TileId>,
+    /// The number of tries at placing the structure in each chunk.
+    pub attempts: u32,
+    /// The chance of each try placing the structure.
+    pub chance: f64,
+}
+
+/// Places structures on the surface, anchored on the tile above a random surface tile.
+///
+/// Every structure belongs to the chunk holding the surface it stands on, so it is placed exactly
+/// once no matter which order chunks generate in. Parts of it reaching into other chunks are queued
+/// through [`GenContext::place`] until those chunks exist.
+#[derive(Default)]
+pub struct Structures {
+    placements: Vec<StructurePlacement>,
+}
+
+impl Structures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_structure(mut self, placement: StructurePlacement) -> Self {
+        self.placements.push(placement);
+        self
+    }
+}
+
+impl GenStage for Structures {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let size = CHUNK_SIZE as i32;
+
+        for (i, placement) in self.placements.iter().enumerate() {
+            let mut rng = ctx.rng(STRUCTURE_SALT + i as u64);
+            for _ in 0..placement.attempts {
+                // Always roll everything, so one failed try doesn't shift the rolls of the rest.
+                let (x, z) = (rng.range(0..size) as usize, rng.range(0..size) as usize);
+                if !rng.chance(placement.chance) {
+                    continue;
+                }
+
+                let Some(y) = (0..CHUNK_SIZE).rev().find(|y| {
+                    chunk
+                        .get([x, *y, z])
+                        .is_some_and(|t| placement.on.contains(&t))
+                        && ctx.air_above_by(chunk, [x, *y, z], 1)
+                }) else {
+                    continue;
+                };
+
+                let anchor = ctx.world_pos([x, y, z]) + IVec3::Y;
+                for (pos, tile) in placement.structure.tiles_at(anchor) {
+                    ctx.place(chunk, pos, tile);
+                }
+            }
+        }
+    }
+}
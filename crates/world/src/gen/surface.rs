@@ -0,0 +1,72 @@
+use glam::IVec3;
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE},
+    tile::TileId,
+};
+
+use super::{GenContext, GenStage};
+
+const SCATTER_SALT: u64 = 30;
+
+/// Covers exposed terrain with a top tile over a few layers of another, like grass over dirt.
+pub struct SurfaceLayers {
+    pub top: TileId,
+    pub under: TileId,
+    /// The number of `under` tiles below the top.
+    pub depth: usize,
+    /// The tile that is covered, usually the terrain's fill tile.
+    pub replaces: TileId,
+}
+
+impl GenStage for SurfaceLayers {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]) != Some(self.replaces) {
+                        continue;
+                    }
+
+                    let Some(depth) =
+                        (1..=self.depth + 1).find(|k| ctx.air_above_by(chunk, [x, y, z], *k))
+                    else {
+                        continue;
+                    };
+                    let tile = match depth {
+                        1 => self.top,
+                        _ => self.under,
+                    };
+                    chunk.set([x, y, z], Some(tile));
+                }
+            }
+        }
+    }
+}
+
+/// Randomly places a tile on top of another, like flowers on grass.
+pub struct Scatter {
+    pub tile: TileId,
+    pub on: TileId,
+    /// The chance of placing the tile on each free surface tile.
+    pub chance: f64,
+}
+
+impl GenStage for Scatter {
+    fn apply(&self, ctx: &GenContext, chunk: &mut Chunk) {
+        let mut rng = ctx.rng(SCATTER_SALT ^ ((self.tile.0 as u64) << 16));
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    if chunk.get([x, y, z]) == Some(self.on)
+                        && ctx.air_above_by(chunk, [x, y, z], 1)
+                        && rng.chance(self.chance)
+                    {
+                        let above = ctx.world_pos([x, y, z]) + IVec3::Y;
+                        ctx.place(chunk, above, self.tile);
+                    }
+                }
+            }
+        }
+    }
+}
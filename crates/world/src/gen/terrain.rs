@@ -0,0 +1,64 @@
+use glam::IVec3;
+use noise::{NoiseFn, Perlin};
+
+use super::{noise_seed, TerrainShape};
+
+const DENSITY_SALT: u64 = 1;
+const HEIGHT_SALT: u64 = 2;
+
+/// 3D noise terrain, solid wherever the noise is above a threshold.
+/// Makes floating islands and overhangs.
+pub struct DensityTerrain {
+    perlin: Perlin,
+    pub frequency: f64,
+    pub threshold: f64,
+}
+
+impl DensityTerrain {
+    pub fn new(seed: u64, frequency: f64, threshold: f64) -> Self {
+        Self {
+            perlin: Perlin::new(noise_seed(seed, DENSITY_SALT)),
+            frequency,
+            threshold,
+        }
+    }
+}
+
+impl TerrainShape for DensityTerrain {
+    fn solid(&self, pos: IVec3) -> bool {
+        let pos = pos.as_dvec3() * self.frequency;
+        self.perlin.get(pos.to_array()) >= self.threshold
+    }
+}
+
+/// 2D noise terrain, solid below a height that rolls around `base_height`.
+pub struct HeightmapTerrain {
+    perlin: Perlin,
+    pub base_height: f64,
+    pub amplitude: f64,
+    pub frequency: f64,
+}
+
+impl HeightmapTerrain {
+    pub fn new(seed: u64, base_height: f64, amplitude: f64, frequency: f64) -> Self {
+        Self {
+            perlin: Perlin::new(noise_seed(seed, HEIGHT_SALT)),
+            base_height,
+            amplitude,
+            frequency,
+        }
+    }
+
+    pub fn height(&self, x: i32, z: i32) -> f64 {
+        let noise = self
+            .perlin
+            .get([x as f64 * self.frequency, z as f64 * self.frequency]);
+        self.base_height + noise * self.amplitude
+    }
+}
+
+impl TerrainShape for HeightmapTerrain {
+    fn solid(&self, pos: IVec3) -> bool {
+        (pos.y as f64) < self.height(pos.x, pos.z)
+    }
+}
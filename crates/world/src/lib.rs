@@ -1,5 +1,21 @@
 pub mod chunk;
+pub mod edit;
+pub mod fluid;
+pub mod gen;
+pub mod light;
+pub mod lod;
+pub mod mesher;
+pub mod palette;
+pub mod physics;
+pub mod raycast;
+pub mod region;
+pub mod save;
+pub mod shape;
+pub mod streamer;
+pub mod structure;
 
 pub mod tile;
+pub mod visibility;
+pub mod vox;
 
 pub mod world;
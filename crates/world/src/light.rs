@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+
+use glam::IVec3;
+use wgpu::naga::FastHashSet;
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE, FACE_OFFSETS},
+    tile::{TileId, TileRegistry},
+    world::World,
+};
+
+/// The brightest light level, that of open sky or the brightest lamp.
+pub const MAX_LIGHT: u8 = 15;
+
+/// How much more light dims spreading into a fluid tile than into open air, so deep water gets dark.
+pub const FLUID_FALLOFF: u8 = 1;
+
+/// Tiles store sunlight and block light separately, packed into the high and low half of a byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LightChannel {
+    /// Light from the sky, which falls straight down through open air without dimming.
+    Sun,
+    /// Light emitted by tiles such as lamps.
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [Self; 2] = [Self::Sun, Self::Block];
+
+    pub fn unpack(self, packed: u8) -> u8 {
+        match self {
+            Self::Sun => packed >> 4,
+            Self::Block => packed & 0xF,
+        }
+    }
+
+    pub fn pack(self, packed: u8, level: u8) -> u8 {
+        match self {
+            Self::Sun => (packed & 0xF) | (level << 4),
+            Self::Block => (packed & 0xF0) | level,
+        }
+    }
+}
+
+/// How bright a light level looks, from almost black at 0 to 1 at [`MAX_LIGHT`].
+pub fn brightness(level: u8) -> f32 {
+    0.8f32.powi((MAX_LIGHT - level) as i32)
+}
+
+/// The sun and block brightness baked into the vertices of a face with the given packed light.
+pub fn vertex_light(packed: u8) -> [f32; 2] {
+    LightChannel::ALL.map(|channel| brightness(channel.unpack(packed)))
+}
+
+/// Lights a chunk on its own, as if the sky was open above it and nothing was around it.
+/// [`World::spread_light`] then fixes up the light across its borders once it is in a world.
+pub fn light_chunk(chunk: &mut Chunk, pos: IVec3, registry: &TileRegistry) {
+    let origin = World::chunk_origin(pos);
+    let mut propagator = Propagator::new(registry);
+
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for y in (0..CHUNK_SIZE).rev() {
+                if !propagator.clear(chunk.get([x, y, z])) {
+                    break;
+                }
+                chunk.set_light([x, y, z], LightChannel::Sun, MAX_LIGHT);
+                propagator.add(origin + loc_offset([x, y, z]), LightChannel::Sun);
+            }
+        }
+    }
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let emission = propagator.emission(chunk.get([x, y, z]));
+                if emission > 0 {
+                    chunk.set_light([x, y, z], LightChannel::Block, emission);
+                    propagator.add(origin + loc_offset([x, y, z]), LightChannel::Block);
+                }
+            }
+        }
+    }
+
+    propagator.spread(&mut ChunkVolume { chunk, origin });
+}
+
+impl World {
+    /// The light level at a world position, or `None` if its chunk isn't loaded.
+    pub fn light_at(&self, pos: IVec3, channel: LightChannel) -> Option<u8> {
+        let (chunk, loc) = Self::split_pos(pos);
+        Some(self.chunk(chunk)?.light(loc, channel))
+    }
+
+    /// Spreads light across the borders of a chunk lit by [`light_chunk`] which was just inserted,
+    /// returning every chunk whose light changed.
+    pub fn spread_light(&mut self, pos: IVec3, registry: &TileRegistry) -> FastHashSet<IVec3> {
+        let mut propagator = Propagator::new(registry);
+        let origin = Self::chunk_origin(pos);
+        let last = CHUNK_SIZE as i32 - 1;
+
+        // Light flows both ways over each border.
+        for offset in FACE_OFFSETS {
+            let offset = IVec3::from(offset);
+            let axis = offset.to_array().iter().position(|v| *v != 0).unwrap();
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for i in 0..=last {
+                for j in 0..=last {
+                    let mut inside = IVec3::ZERO;
+                    inside[axis] = if offset[axis] > 0 { last } else { 0 };
+                    inside[u] = i;
+                    inside[v] = j;
+
+                    for channel in LightChannel::ALL {
+                        propagator.add(origin + inside, channel);
+                        propagator.add(origin + inside + offset, channel);
+                    }
+                }
+            }
+        }
+
+        // The chunk was lit as if under open sky, and the chunk below may have been lit the same way.
+        let mut unlit = vec![];
+        for (upper, lower) in [(pos + IVec3::Y, pos), (pos, pos - IVec3::Y)] {
+            let (Some(upper_chunk), Some(lower_chunk)) = (self.chunk(upper), self.chunk(lower))
+            else {
+                continue;
+            };
+
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let sky = upper_chunk.light([x, 0, z], LightChannel::Sun) == MAX_LIGHT;
+                    let top = [x, CHUNK_SIZE - 1, z];
+                    if !sky && lower_chunk.light(top, LightChannel::Sun) == MAX_LIGHT {
+                        unlit.push(Self::chunk_origin(lower) + loc_offset(top));
+                    }
+                }
+            }
+        }
+
+        let mut volume = WorldVolume::new(self);
+        for pos in unlit {
+            propagator.remove(&mut volume, pos, LightChannel::Sun);
+        }
+        propagator.spread(&mut volume);
+        volume.changed
+    }
+
+    /// Updates the light around a tile after it was placed or removed, returning every chunk whose
+    /// light changed.
+    pub fn update_light(&mut self, pos: IVec3, registry: &TileRegistry) -> FastHashSet<IVec3> {
+        self.update_lights([pos], registry)
+    }
+
+    /// Updates the light around many tiles which changed at once, spreading it only once.
+    pub fn update_lights(
+        &mut self,
+        positions: impl IntoIterator<Item = IVec3>,
+        registry: &TileRegistry,
+    ) -> FastHashSet<IVec3> {
+        let positions = positions
+            .into_iter()
+            .filter(|pos| self.is_loaded(Self::split_pos(*pos).0))
+            .collect::<Vec<_>>();
+
+        let mut propagator = Propagator::new(registry);
+        let mut volume = WorldVolume::new(self);
+        for pos in &positions {
+            for channel in LightChannel::ALL {
+                propagator.remove(&mut volume, *pos, channel);
+                for offset in FACE_OFFSETS {
+                    propagator.add(*pos + IVec3::from(offset), channel);
+                }
+            }
+        }
+
+        for pos in positions {
+            let (chunk, loc) = Self::split_pos(pos);
+            let tile = volume.world.get_tile(pos);
+            let emission = propagator.emission(tile);
+            if emission > 0 {
+                volume.set_light(pos, LightChannel::Block, emission);
+                propagator.add(pos, LightChannel::Block);
+            }
+
+            // Tiles below an unloaded chunk are treated as being under open sky.
+            let under_sky = loc[1] == CHUNK_SIZE - 1 && !volume.world.is_loaded(chunk + IVec3::Y);
+            if under_sky && propagator.clear(tile) {
+                volume.set_light(pos, LightChannel::Sun, MAX_LIGHT);
+                propagator.add(pos, LightChannel::Sun);
+            }
+        }
+
+        propagator.spread(&mut volume);
+        volume.changed
+    }
+}
+
+fn loc_offset(loc: [usize; 3]) -> IVec3 {
+    IVec3::new(loc[0] as i32, loc[1] as i32, loc[2] as i32)
+}
+
+/// Somewhere light can be spread through, addressed by world position.
+trait LightVolume {
+    /// The tile at a position, or `None` if it is outside the volume.
+    fn tile(&self, pos: IVec3) -> Option<Option<TileId>>;
+    fn light(&self, pos: IVec3, channel: LightChannel) -> u8;
+    fn set_light(&mut self, pos: IVec3, channel: LightChannel, level: u8);
+}
+
+/// A single chunk, which light can't leave.
+struct ChunkVolume<'a> {
+    chunk: &'a mut Chunk,
+    origin: IVec3,
+}
+
+impl ChunkVolume<'_> {
+    fn loc(&self, pos: IVec3) -> Option<[usize; 3]> {
+        let local = pos - self.origin;
+        let size = CHUNK_SIZE as i32;
+        (local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(size)).all()).then_some([
+            local.x as usize,
+            local.y as usize,
+            local.z as usize,
+        ])
+    }
+}
+
+impl LightVolume for ChunkVolume<'_> {
+    fn tile(&self, pos: IVec3) -> Option<Option<TileId>> {
+        Some(self.chunk.get(self.loc(pos)?))
+    }
+
+    fn light(&self, pos: IVec3, channel: LightChannel) -> u8 {
+        self.loc(pos)
+            .map_or(0, |loc| self.chunk.light(loc, channel))
+    }
+
+    fn set_light(&mut self, pos: IVec3, channel: LightChannel, level: u8) {
+        if let Some(loc) = self.loc(pos) {
+            self.chunk.set_light(loc, channel, level);
+        }
+    }
+}
+
+/// Every loaded chunk in a world, keeping track of the chunks whose light changed.
+struct WorldVolume<'a> {
+    world: &'a mut World,
+    changed: FastHashSet<IVec3>,
+}
+
+impl<'a> WorldVolume<'a> {
+    fn new(world: &'a mut World) -> Self {
+        Self {
+            world,
+            changed: FastHashSet::default(),
+        }
+    }
+}
+
+impl LightVolume for WorldVolume<'_> {
+    fn tile(&self, pos: IVec3) -> Option<Option<TileId>> {
+        let (chunk, loc) = World::split_pos(pos);
+        Some(self.world.chunk(chunk)?.get(loc))
+    }
+
+    fn light(&self, pos: IVec3, channel: LightChannel) -> u8 {
+        self.world.light_at(pos, channel).unwrap_or(0)
+    }
+
+    fn set_light(&mut self, pos: IVec3, channel: LightChannel, level: u8) {
+        let (chunk, loc) = World::split_pos(pos);
+        if self.world.light_at(pos, channel).is_none_or(|l| l == level) {
+            return;
+        }
+        self.world
+            .chunk_mut(chunk)
+            .unwrap()
+            .set_light(loc, channel, level);
+        self.changed.insert(chunk);
+
+        // Faces of the neighboring chunks are lit by tiles on the border.
+        if loc.iter().any(|l| *l == 0 || *l == CHUNK_SIZE - 1) {
+            self.world.mark_dirty(pos);
+        }
+    }
+}
+
+/// Breadth first flood fill of light, first clearing light that lost its source, then spreading
+/// light outwards from every lit tile queued.
+struct Propagator<'a> {
+    registry: &'a TileRegistry,
+    adds: VecDeque<(IVec3, LightChannel)>,
+    removals: VecDeque<(IVec3, LightChannel, u8)>,
+}
+
+impl<'a> Propagator<'a> {
+    fn new(registry: &'a TileRegistry) -> Self {
+        Self {
+            registry,
+            adds: VecDeque::new(),
+            removals: VecDeque::new(),
+        }
+    }
+
+    fn passes(&self, tile: Option<TileId>) -> bool {
+        tile.is_none_or(|t| !self.registry.get(t).occludes())
+    }
+
+    /// How many levels light loses spreading into a tile it passes through.
+    fn falloff(&self, tile: Option<TileId>) -> u8 {
+        match tile.is_some_and(|t| self.registry.get(t).fluid.is_some()) {
+            true => 1 + FLUID_FALLOFF,
+            false => 1,
+        }
+    }
+
+    /// Whether full sunlight falls straight through a tile without dimming.
+    fn clear(&self, tile: Option<TileId>) -> bool {
+        self.passes(tile) && self.falloff(tile) == 1
+    }
+
+    fn emission(&self, tile: Option<TileId>) -> u8 {
+        tile.map_or(0, |t| self.registry.get(t).light.min(MAX_LIGHT))
+    }
+
+    /// Queues a lit tile to spread its light to its neighbors.
+    fn add(&mut self, pos: IVec3, channel: LightChannel) {
+        self.adds.push_back((pos, channel));
+    }
+
+    /// Clears the light of a tile, queueing any light that came from it to be cleared too.
+    fn remove(&mut self, volume: &mut impl LightVolume, pos: IVec3, channel: LightChannel) {
+        let level = volume.light(pos, channel);
+        if level > 0 {
+            volume.set_light(pos, channel, 0);
+            self.removals.push_back((pos, channel, level));
+        }
+    }
+
+    fn spread(&mut self, volume: &mut impl LightVolume) {
+        while let Some((pos, channel, level)) = self.removals.pop_front() {
+            for offset in FACE_OFFSETS {
+                let offset = IVec3::from(offset);
+                let neighbor = pos + offset;
+                let Some(tile) = volume.tile(neighbor) else {
+                    continue;
+                };
+
+                let neighbor_level = volume.light(neighbor, channel);
+                if neighbor_level == 0 {
+                    continue;
+                }
+
+                // Dimmer light, and full sunlight straight below full sunlight, came from this tile.
+                let falling = channel == LightChannel::Sun && offset == IVec3::NEG_Y;
+                if neighbor_level < level || (falling && level == MAX_LIGHT) {
+                    volume.set_light(neighbor, channel, 0);
+                    self.removals.push_back((neighbor, channel, neighbor_level));
+
+                    let emission = self.emission(tile);
+                    if channel == LightChannel::Block && emission > 0 {
+                        volume.set_light(neighbor, channel, emission);
+                        self.adds.push_back((neighbor, channel));
+                    }
+                } else {
+                    // Brighter light has its own source, and may need to fill the cleared tiles.
+                    self.adds.push_back((neighbor, channel));
+                }
+            }
+        }
+
+        while let Some((pos, channel)) = self.adds.pop_front() {
+            let level = volume.light(pos, channel);
+            if level <= 1 {
+                continue;
+            }
+
+            for offset in FACE_OFFSETS {
+                let offset = IVec3::from(offset);
+                let neighbor = pos + offset;
+                let Some(tile) = volume.tile(neighbor).filter(|t| self.passes(*t)) else {
+                    continue;
+                };
+
+                let falling = channel == LightChannel::Sun && offset == IVec3::NEG_Y;
+                let spread = match falling && level == MAX_LIGHT && self.clear(tile) {
+                    true => MAX_LIGHT,
+                    false => level.saturating_sub(self.falloff(tile)),
+                };
+                if volume.light(neighbor, channel) < spread {
+                    volume.set_light(neighbor, channel, spread);
+                    self.adds.push_back((neighbor, channel));
+                }
+            }
+        }
+    }
+}
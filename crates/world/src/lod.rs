@@ -0,0 +1,242 @@
+use render::mesh::builder::MeshBuilder;
+
+use crate::{
+    chunk::{
+        face_visible, side_visible, Chunk, ChunkNeighbors, CHUNK_SIZE, FACE_OFFSETS, FULL_SIDE,
+    },
+    light,
+    mesher::vertex_ao,
+    tile::{TileId, TileRegistry, TileState},
+};
+
+/// The coarsest level of detail, where each cell covers `2^MAX_LOD` tiles along each axis.
+pub const MAX_LOD: u8 = 4;
+
+/// The level of detail for a chunk `distance` chunks away from the camera.
+/// Chunks closer than `lod_distance` use full detail, and the level goes up by one each time the
+/// distance doubles after that.
+pub fn lod_level(distance: f32, lod_distance: f32) -> u8 {
+    if distance < lod_distance || lod_distance <= 0.0 {
+        return 0;
+    }
+    ((distance / lod_distance).log2() as u8 + 1).min(MAX_LOD)
+}
+
+/// The tile a cell of `2^level` tiles along each axis is drawn as, with `cell` in cell coordinates.
+///
+/// A cell is filled when at least half of it is solid, and takes the most common tile of its highest
+/// filled layer, so surfaces keep their top tile. Tiles which aren't solid, like flowers, are dropped.
+/// Cells are always drawn in the tile's default state.
+pub(crate) fn cell(
+    chunk: &Chunk,
+    registry: &TileRegistry,
+    level: u8,
+    cell: [usize; 3],
+) -> Option<TileId> {
+    let size = 1 << level;
+    let min = cell.map(|c| c * size);
+    let solid = |loc: [usize; 3]| chunk.get(loc).filter(|t| registry.get(*t).solid);
+
+    let mut filled = 0;
+    let mut top = None;
+    for y in (0..size).rev() {
+        let mut counts: Vec<(TileId, usize)> = vec![];
+        for x in 0..size {
+            for z in 0..size {
+                let Some(tile) = solid([min[0] + x, min[1] + y, min[2] + z]) else {
+                    continue;
+                };
+                filled += 1;
+                match counts.iter_mut().find(|(t, _)| *t == tile) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((tile, 1)),
+                }
+            }
+        }
+
+        if top.is_none() {
+            top = counts
+                .into_iter()
+                .max_by_key(|(_, count)| *count)
+                .map(|(t, _)| t);
+        }
+    }
+
+    (filled * 2 >= size * size * size).then_some(top).flatten()
+}
+
+/// Meshes a chunk downsampled to a level of detail, one quad per visible cell face.
+///
+/// Faces on the chunk border are culled against each neighbor at its own level of detail, from
+/// [`ChunkNeighbors::lod`]. A face is only hidden when the neighbor's cells cover it entirely, so
+/// chunks of different detail never leave gaps between them. Both sides have to be remeshed when
+/// either changes its level.
+pub(crate) fn mesh(
+    chunk: &Chunk,
+    registry: &TileRegistry,
+    neighbors: &ChunkNeighbors,
+    level: u8,
+    builder: &mut MeshBuilder,
+    offset: [f32; 3],
+) {
+    let size = 1 << level;
+    let cells = CHUNK_SIZE >> level;
+    let index = |c: [usize; 3]| c[0] + c[1] * cells + c[2] * cells * cells;
+
+    let mut downsampled = vec![None; cells * cells * cells];
+    for x in 0..cells {
+        for y in 0..cells {
+            for z in 0..cells {
+                downsampled[index([x, y, z])] = cell(chunk, registry, level, [x, y, z]);
+            }
+        }
+    }
+
+    for x in 0..cells {
+        for y in 0..cells {
+            for z in 0..cells {
+                let Some(tile) = downsampled[index([x, y, z])] else {
+                    continue;
+                };
+                let min = [x, y, z].map(|c| c * size);
+
+                for (face, dir) in FACE_OFFSETS.into_iter().enumerate() {
+                    let next = [x as i32 + dir[0], y as i32 + dir[1], z as i32 + dir[2]];
+                    let visible = match next.iter().all(|v| (0..cells as i32).contains(v)) {
+                        true => face_visible(
+                            registry,
+                            tile,
+                            downsampled[index(next.map(|v| v as usize))],
+                        ),
+                        false => border_visible(registry, neighbors, tile, min, size, face),
+                    };
+                    if !visible {
+                        continue;
+                    }
+
+                    builder.add_shaded(
+                        [0, 1, 2].map(|i| offset[i] + min[i] as f32),
+                        face,
+                        [size as f32; 3],
+                        registry.texture_layer(tile, TileState::default(), face),
+                        light::vertex_light(face_light(chunk, neighbors, min, size, face)),
+                        vertex_ao([3; 4]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The cells of `neighbor`'s layer touching the chunk across `face`, at a level of detail, indexed
+/// by [`border_index`].
+pub(crate) fn border_layer(
+    neighbor: &Chunk,
+    registry: &TileRegistry,
+    level: u8,
+    face: usize,
+) -> Vec<Option<TileId>> {
+    let cells = CHUNK_SIZE >> level;
+    let dir = FACE_OFFSETS[face];
+    let axis = dir.iter().position(|v| *v != 0).unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+    let mut layer = vec![None; cells * cells];
+    for i in 0..cells {
+        for j in 0..cells {
+            let mut loc = [0; 3];
+            loc[axis] = match dir[axis] > 0 {
+                true => 0,
+                false => cells - 1,
+            };
+            loc[u] = i;
+            loc[v] = j;
+            layer[border_index(face, level, loc)] = cell(neighbor, registry, level, loc);
+        }
+    }
+    layer
+}
+
+/// The index in a [`border_layer`] of the neighbor's cell holding `loc`, in cell coordinates.
+fn border_index(face: usize, level: u8, loc: [usize; 3]) -> usize {
+    let axis = FACE_OFFSETS[face].iter().position(|v| *v != 0).unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    loc[u] + loc[v] * (CHUNK_SIZE >> level)
+}
+
+/// Whether the face of a cell on the chunk border shows, given the neighbor's level of detail.
+/// `min` is the tile the cell starts at and `size` its width in tiles.
+pub(crate) fn border_visible(
+    registry: &TileRegistry,
+    neighbors: &ChunkNeighbors,
+    tile: TileId,
+    min: [usize; 3],
+    size: usize,
+    face: usize,
+) -> bool {
+    let Some(neighbor) = neighbors.get(face) else {
+        return true;
+    };
+    let level = neighbors.lod(face);
+    let neighbor_size = 1 << level;
+
+    // The layer of the neighbor touching the face, in the neighbor's tiles.
+    let dir = FACE_OFFSETS[face];
+    let axis = dir.iter().position(|v| *v != 0).unwrap();
+    let mut start = min;
+    start[axis] = match dir[axis] > 0 {
+        true => 0,
+        false => CHUNK_SIZE - 1,
+    };
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let layer = match level {
+        0 => None,
+        _ => neighbors.border_layer(registry, face),
+    };
+
+    // Visible as long as any of the neighbor's cells along the face doesn't hide it.
+    let step = neighbor_size.min(size);
+    (0..size).step_by(step).any(|i| {
+        (0..size).step_by(step).any(|j| {
+            let mut loc = start;
+            loc[u] += i;
+            loc[v] += j;
+            match layer {
+                None => side_visible(registry, tile, neighbor.tile_state(loc), face, FULL_SIDE),
+                Some(layer) => {
+                    let other = layer[border_index(face, level, loc.map(|l| l / neighbor_size))];
+                    face_visible(registry, tile, other)
+                }
+            }
+        })
+    })
+}
+
+/// The brightest light of each channel shining on any tile of a cell's face.
+fn face_light(
+    chunk: &Chunk,
+    neighbors: &ChunkNeighbors,
+    min: [usize; 3],
+    size: usize,
+    face: usize,
+) -> u8 {
+    let dir = FACE_OFFSETS[face];
+    let axis = dir.iter().position(|v| *v != 0).unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut start = min;
+    if dir[axis] > 0 {
+        start[axis] += size - 1;
+    }
+
+    let mut light = 0;
+    for i in 0..size {
+        for j in 0..size {
+            let mut loc = start;
+            loc[u] += i;
+            loc[v] += j;
+            let packed = chunk.face_light(neighbors, loc, face);
+            light = (light & 0xF0).max(packed & 0xF0) | (light & 0xF).max(packed & 0xF);
+        }
+    }
+    light
+}
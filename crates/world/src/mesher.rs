@@ -0,0 +1,127 @@
+use render::mesh::builder::MeshBuilder;
+
+use crate::{
+    chunk::{Chunk, ChunkNeighbors, CHUNK_SIZE, FACE_OFFSETS},
+    light,
+    shape::TileShape,
+    tile::{TileId, TileRegistry, TileState},
+};
+
+/// How a chunk turns its tiles into faces.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MeshMode {
+    /// One quad per visible tile face.
+    #[default]
+    Simple,
+    /// Merges neighboring faces of the same tile into larger quads.
+    Greedy,
+}
+
+/// What a face is drawn with. Faces only merge if they share their tile, state, light and ambient
+/// occlusion.
+type FaceKey = (TileId, TileState, u8, [u8; 4]);
+
+/// Greedy meshes a chunk, one slice of faces at a time.
+/// Each slice is a mask of the visible faces on it, which is then covered with the largest rectangles
+/// of matching tiles that fit.
+pub(crate) fn greedy(
+    chunk: &Chunk,
+    registry: &TileRegistry,
+    neighbors: &ChunkNeighbors,
+    builder: &mut MeshBuilder,
+    offset: [f32; 3],
+) {
+    let mut mask: Vec<Option<FaceKey>> = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+
+    for (face, dir) in FACE_OFFSETS.into_iter().enumerate() {
+        // The axis the face points along, and the two axes the face lies on.
+        let normal = dir.iter().position(|v| *v != 0).unwrap();
+        let u = (normal + 1) % 3;
+        let v = (normal + 2) % 3;
+
+        for depth in 0..CHUNK_SIZE {
+            for j in 0..CHUNK_SIZE {
+                for i in 0..CHUNK_SIZE {
+                    let mut loc = [0; 3];
+                    loc[normal] = depth;
+                    loc[u] = i;
+                    loc[v] = j;
+
+                    mask[i + j * CHUNK_SIZE] = chunk
+                        .get(loc)
+                        .filter(|tile| drawn_as_cube(registry, *tile))
+                        .filter(|tile| chunk.face_visible(registry, neighbors, *tile, loc, face))
+                        .map(|tile| {
+                            (
+                                tile,
+                                chunk.state(loc),
+                                chunk.face_light(neighbors, loc, face),
+                                chunk.face_ao(registry, neighbors, loc, face),
+                            )
+                        });
+                }
+            }
+
+            for j in 0..CHUNK_SIZE {
+                let mut i = 0;
+                while i < CHUNK_SIZE {
+                    let Some(key) = mask[i + j * CHUNK_SIZE] else {
+                        i += 1;
+                        continue;
+                    };
+
+                    // Grow the quad along u, then along v while every tile in the row matches.
+                    let mut width = 1;
+                    while i + width < CHUNK_SIZE && mask[i + width + j * CHUNK_SIZE] == Some(key) {
+                        width += 1;
+                    }
+
+                    let mut height = 1;
+                    while j + height < CHUNK_SIZE
+                        && (i..i + width).all(|k| mask[k + (j + height) * CHUNK_SIZE] == Some(key))
+                    {
+                        height += 1;
+                    }
+
+                    for dj in 0..height {
+                        for di in 0..width {
+                            mask[i + di + (j + dj) * CHUNK_SIZE] = None;
+                        }
+                    }
+
+                    let mut pos = offset;
+                    pos[normal] += depth as f32;
+                    pos[u] += i as f32;
+                    pos[v] += j as f32;
+
+                    let mut size = [1.0; 3];
+                    size[u] = width as f32;
+                    size[v] = height as f32;
+
+                    let (tile, state, light, ao) = key;
+                    builder.add_shaded(
+                        pos,
+                        face,
+                        size,
+                        registry.texture_layer(tile, state, face),
+                        light::vertex_light(light),
+                        vertex_ao(ao),
+                    );
+                    i += width;
+                }
+            }
+        }
+    }
+}
+
+/// Whether a tile is drawn by the cube meshers. Fluids and other shapes are meshed on their own by
+/// [`crate::fluid`] and [`crate::shape`].
+pub(crate) fn drawn_as_cube(registry: &TileRegistry, tile: TileId) -> bool {
+    let def = registry.get(tile);
+    def.shape == TileShape::Cube && def.fluid.is_none()
+}
+
+/// How bright each corner of a face is, given its ambient occlusion levels from [`Chunk::face_ao`].
+pub(crate) fn vertex_ao(ao: [u8; 4]) -> [f32; 4] {
+    ao.map(|level| 0.4 + 0.2 * level as f32)
+}
@@ -0,0 +1,276 @@
+/// Stores a fixed number of values as bit packed indices into a palette of the distinct values.
+/// The indices start out empty while every value is the same, and grow as more distinct values are
+/// added, so a chunk of a few tile types only takes a couple of bits per tile.
+#[derive(Clone, Debug)]
+pub struct PalettedStorage<T> {
+    palette: Vec<T>,
+    /// Bits per index, always a power of two so an index never straddles two words.
+    /// Zero when the storage is uniform, in which case `data` is empty.
+    bits: u32,
+    data: Vec<u64>,
+    len: usize,
+}
+
+impl<T: Copy + PartialEq> PalettedStorage<T> {
+    /// Creates a storage of `len` values, all set to `value`.
+    pub fn new(len: usize, value: T) -> Self {
+        Self {
+            palette: vec![value],
+            bits: 0,
+            data: vec![],
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> T {
+        self.palette[self.index(i)]
+    }
+
+    pub fn set(&mut self, i: usize, value: T) {
+        let index = match self.palette.iter().position(|v| *v == value) {
+            Some(index) => index,
+            None => {
+                self.palette.push(value);
+                let needed = bits_for(self.palette.len());
+                if needed > self.bits {
+                    self.resize(needed);
+                }
+                self.palette.len() - 1
+            }
+        };
+
+        if self.bits == 0 {
+            // Uniform storage only ever holds palette entry 0.
+            return;
+        }
+
+        let (word, shift) = self.position(i);
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        self.data[word] = (self.data[word] & !mask) | ((index as u64) << shift);
+    }
+
+    /// Sets every value, dropping back to the uniform representation.
+    pub fn fill(&mut self, value: T) {
+        self.palette = vec![value];
+        self.bits = 0;
+        self.data = vec![];
+    }
+
+    /// Returns the single value held, if every value is the same.
+    pub fn uniform(&self) -> Option<T> {
+        match self.bits {
+            0 => Some(self.palette[0]),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds a storage from the parts returned by [`Self::palette`], [`Self::bits_per_value`] and
+    /// [`Self::raw_data`], returning `None` if they don't form a valid storage.
+    pub fn from_raw(len: usize, palette: Vec<T>, bits: u32, data: Vec<u64>) -> Option<Self> {
+        if palette.is_empty() || bits > 32 || (bits != 0 && !bits.is_power_of_two()) {
+            return None;
+        }
+
+        let expected = match bits {
+            0 => 0,
+            _ => len.div_ceil((u64::BITS / bits) as usize),
+        };
+        if data.len() != expected || bits_for(palette.len()) > bits {
+            return None;
+        }
+
+        let storage = Self {
+            palette,
+            bits,
+            data,
+            len,
+        };
+        if (0..len).any(|i| storage.index(i) >= storage.palette.len()) {
+            return None;
+        }
+        Some(storage)
+    }
+
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    pub fn bits_per_value(&self) -> u32 {
+        self.bits
+    }
+
+    /// The packed indices, empty while the storage is uniform.
+    pub fn raw_data(&self) -> &[u64] {
+        &self.data
+    }
+
+    /// Drops palette entries which are no longer used, shrinking the indices if possible.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len {
+            used[self.index(i)] = true;
+        }
+        if used.iter().all(|u| *u) {
+            return;
+        }
+
+        let values = (0..self.len).map(|i| self.get(i)).collect::<Vec<_>>();
+        self.fill(values[0]);
+        for (i, value) in values.into_iter().enumerate() {
+            self.set(i, value);
+        }
+    }
+
+    /// The number of bytes the storage has allocated on the heap.
+    pub fn heap_usage(&self) -> usize {
+        self.palette.capacity() * size_of::<T>() + self.data.capacity() * size_of::<u64>()
+    }
+
+    fn index(&self, i: usize) -> usize {
+        assert!(i < self.len, "index {i} out of bounds of {}", self.len);
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let (word, shift) = self.position(i);
+        ((self.data[word] >> shift) & ((1u64 << self.bits) - 1)) as usize
+    }
+
+    fn position(&self, i: usize) -> (usize, u32) {
+        let per_word = (u64::BITS / self.bits) as usize;
+        (i / per_word, (i % per_word) as u32 * self.bits)
+    }
+
+    fn resize(&mut self, bits: u32) {
+        let indices = (0..self.len).map(|i| self.index(i)).collect::<Vec<_>>();
+
+        self.bits = bits;
+        let per_word = (u64::BITS / bits) as usize;
+        self.data = vec![0; self.len.div_ceil(per_word)];
+
+        for (i, index) in indices.into_iter().enumerate() {
+            let (word, shift) = self.position(i);
+            self.data[word] |= (index as u64) << shift;
+        }
+    }
+}
+
+/// The smallest power of two bit count that can index a palette of `len` entries.
+fn bits_for(len: usize) -> u32 {
+    match len {
+        0 | 1 => 0,
+        _ => (usize::BITS - (len - 1).leading_zeros()).next_power_of_two(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: usize = 1000;
+
+    #[test]
+    fn set_grows_through_each_bit_width() {
+        let mut storage = PalettedStorage::new(LEN, 0u32);
+        assert_eq!(storage.bits_per_value(), 0);
+
+        // Each new distinct value grows the indices once the palette outgrows them.
+        for (values, bits) in [(2, 1), (3, 2), (5, 4), (17, 8), (257, 16)] {
+            for value in 0..values {
+                storage.set(value as usize, value);
+            }
+            assert_eq!(storage.bits_per_value(), bits, "{values} values");
+            assert_eq!(storage.palette().len(), values as usize);
+            assert!((0..values).all(|v| storage.get(v as usize) == v));
+            assert!((values as usize..LEN).all(|i| storage.get(i) == 0));
+        }
+    }
+
+    #[test]
+    fn compact_shrinks_back() {
+        let mut storage = PalettedStorage::new(LEN, 0u32);
+        for i in 0..20 {
+            storage.set(i, i as u32);
+        }
+        assert_eq!(storage.bits_per_value(), 8);
+
+        for i in 2..20 {
+            storage.set(i, 0);
+        }
+        storage.compact();
+        assert_eq!(storage.bits_per_value(), 1);
+        assert_eq!(storage.palette(), &[0, 1]);
+        assert_eq!(storage.get(1), 1);
+        assert!((2..LEN).all(|i| storage.get(i) == 0));
+
+        storage.set(1, 0);
+        storage.compact();
+        assert_eq!(storage.uniform(), Some(0));
+        assert!(storage.raw_data().is_empty());
+    }
+
+    #[test]
+    fn fill_makes_uniform() {
+        let mut storage = PalettedStorage::new(LEN, 0u8);
+        assert_eq!(storage.uniform(), Some(0));
+        storage.set(5, 3);
+        assert_eq!(storage.uniform(), None);
+
+        storage.fill(7);
+        assert_eq!(storage.uniform(), Some(7));
+        assert_eq!(storage.bits_per_value(), 0);
+        assert!((0..LEN).all(|i| storage.get(i) == 7));
+    }
+
+    #[test]
+    fn from_raw_round_trips() {
+        let mut storage = PalettedStorage::new(LEN, 0u8);
+        for i in 0..LEN {
+            storage.set(i, (i % 3) as u8);
+        }
+        let raw = PalettedStorage::from_raw(
+            LEN,
+            storage.palette().to_vec(),
+            storage.bits_per_value(),
+            storage.raw_data().to_vec(),
+        )
+        .unwrap();
+        assert!((0..LEN).all(|i| raw.get(i) == storage.get(i)));
+    }
+
+    #[test]
+    fn from_raw_rejects_bad_parts() {
+        let words = LEN.div_ceil(32);
+        assert!(PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 2, vec![0; words]).is_some());
+
+        // Too few or too many words for the length.
+        assert!(PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 2, vec![0; words - 1]).is_none());
+        assert!(PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 2, vec![0; words + 1]).is_none());
+        // Uniform storage has no words.
+        assert!(PalettedStorage::from_raw(LEN, vec![0u8], 0, vec![0]).is_none());
+        // Bit widths which aren't a power of two, or too small for the palette.
+        assert!(
+            PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 3, vec![0; LEN.div_ceil(21)]).is_none()
+        );
+        assert!(
+            PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 1, vec![0; LEN.div_ceil(64)]).is_none()
+        );
+        assert!(PalettedStorage::<u8>::from_raw(LEN, vec![], 0, vec![]).is_none());
+        // Indices past the end of the palette.
+        assert!(
+            PalettedStorage::from_raw(LEN, vec![0u8, 1, 2], 2, vec![u64::MAX; words]).is_none()
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use glam::{IVec3, Vec3, Vec3Swizzles};
+
+use crate::{tile::TileRegistry, world::World};
+
+/// How close boxes can get before they count as touching. Keeps rounding errors from letting a box
+/// sink into the face it was just moved against.
+const EPSILON: f32 = 1e-4;
+
+/// An axis aligned bounding box.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// The box filling a whole tile.
+    pub fn tile(pos: IVec3) -> Self {
+        let min = pos.as_vec3();
+        Self::new(min, min + Vec3::ONE)
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Grows the box to cover everywhere it passes through while moving by `motion`.
+    pub fn expanded(self, motion: Vec3) -> Self {
+        Self::new(
+            self.min + motion.min(Vec3::ZERO),
+            self.max + motion.max(Vec3::ZERO),
+        )
+    }
+
+    /// Whether the boxes overlap. Boxes which only touch don't count.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.overlaps_on(other, axis))
+    }
+
+    fn overlaps_on(&self, other: &Aabb, axis: usize) -> bool {
+        other.max[axis] > self.min[axis] + EPSILON && other.min[axis] < self.max[axis] - EPSILON
+    }
+
+    /// Every tile the box overlaps.
+    pub fn tiles(&self) -> impl Iterator<Item = IVec3> {
+        let min = (self.min + EPSILON).floor().as_ivec3();
+        let max = (self.max - EPSILON).floor().as_ivec3();
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    /// Shortens `motion` along `axis` so the box stops at `other` instead of passing into it.
+    fn clip(&self, other: &Aabb, axis: usize, motion: f32) -> f32 {
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        if !self.overlaps_on(other, a) || !self.overlaps_on(other, b) {
+            return motion;
+        }
+
+        if motion > 0.0 && other.min[axis] >= self.max[axis] - EPSILON {
+            motion.min(other.min[axis] - self.max[axis])
+        } else if motion < 0.0 && other.max[axis] <= self.min[axis] + EPSILON {
+            motion.max(other.max[axis] - self.min[axis])
+        } else {
+            motion
+        }
+    }
+}
+
+impl World {
+    /// The collision boxes of every solid tile overlapping `region`, from the boxes of its model.
+    /// Unloaded chunks are solid, so nothing falls out of the world before it has loaded.
+    pub fn collision_boxes(&self, region: Aabb, registry: &TileRegistry) -> Vec<Aabb> {
+        let mut boxes = vec![];
+        for pos in region.tiles() {
+            let (chunk, _) = World::split_pos(pos);
+            if !self.is_loaded(chunk) {
+                boxes.push(Aabb::tile(pos));
+                continue;
+            }
+
+            let Some(tile) = self.get_tile(pos).filter(|t| registry.get(*t).solid) else {
+                continue;
+            };
+            let model = registry.model(tile, self.get_state(pos));
+            boxes.extend(model.boxes.iter().map(|b| b.translated(pos.as_vec3())));
+        }
+        boxes
+    }
+
+    /// Moves a box through the world, stopping at solid tiles, and returns how far it actually moved.
+    ///
+    /// The motion is resolved one axis at a time, starting with the vertical, so a box blocked on one
+    /// axis still slides along the others. The whole path is checked, so fast boxes can't tunnel.
+    pub fn move_aabb(&self, aabb: Aabb, motion: Vec3, registry: &TileRegistry) -> Vec3 {
+        let boxes = self.collision_boxes(aabb.expanded(motion), registry);
+
+        let mut aabb = aabb;
+        let mut moved = Vec3::ZERO;
+        for axis in [1, 0, 2] {
+            let mut motion = motion[axis];
+            for other in &boxes {
+                motion = aabb.clip(other, axis, motion);
+            }
+
+            let mut offset = Vec3::ZERO;
+            offset[axis] = motion;
+            aabb = aabb.translated(offset);
+            moved[axis] = motion;
+        }
+        moved
+    }
+}
+
+/// A box shaped body which walks through the world, falling under gravity and stepping up ledges.
+#[derive(Copy, Clone, Debug)]
+pub struct Body {
+    /// The center of the bottom of the body.
+    pub position: Vec3,
+    pub velocity: Vec3,
+
+    /// Half the width of the body on the x and z axes.
+    pub half_width: f32,
+    pub height: f32,
+
+    /// Downwards acceleration, in tiles per second squared.
+    pub gravity: f32,
+    /// The fastest the body can fall.
+    pub max_fall_speed: f32,
+    /// How tall a ledge the body walks up without jumping.
+    pub step_height: f32,
+
+    /// Whether the body is standing on something, which it has to be to jump or step up.
+    pub on_ground: bool,
+}
+
+impl Body {
+    pub fn new(position: Vec3, half_width: f32, height: f32) -> Self {
+        Self {
+            position,
+            velocity: Vec3::ZERO,
+            half_width,
+            height,
+            gravity: 28.0,
+            max_fall_speed: 60.0,
+            step_height: 1.0,
+            on_ground: false,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = Vec3::new(self.half_width, 0.0, self.half_width);
+        Aabb::new(
+            self.position - half,
+            self.position + half + Vec3::Y * self.height,
+        )
+    }
+
+    /// Jumps with an upwards speed, if the body is on the ground.
+    pub fn jump(&mut self, speed: f32) {
+        if self.on_ground {
+            self.velocity.y = speed;
+            self.on_ground = false;
+        }
+    }
+
+    /// Applies gravity and moves the body by its velocity over `delta` seconds.
+    /// Velocity along any axis the body was stopped on is cleared.
+    pub fn update(&mut self, world: &World, registry: &TileRegistry, delta: f32) {
+        self.velocity.y = (self.velocity.y - self.gravity * delta).max(-self.max_fall_speed);
+
+        let motion = self.velocity * delta;
+        let aabb = self.aabb();
+        let mut moved = world.move_aabb(aabb, motion, registry);
+
+        // Blocked sideways while on the ground, so try climbing the ledge: move up, then across,
+        // then back down onto it, and keep whichever got further.
+        let blocked = moved.x != motion.x || moved.z != motion.z;
+        if blocked && self.on_ground && self.step_height > 0.0 {
+            let up = world.move_aabb(aabb, Vec3::Y * self.step_height, registry);
+            let raised = aabb.translated(up);
+            let across = world.move_aabb(raised, motion.with_y(0.0), registry);
+            let across_aabb = raised.translated(across);
+            let down =
+                world.move_aabb(across_aabb, Vec3::Y * -(up.y - motion.y.min(0.0)), registry);
+            let stepped = up + across + down;
+
+            if stepped.xz().length_squared() > moved.xz().length_squared() + EPSILON {
+                moved = stepped;
+            }
+        }
+
+        self.on_ground = motion.y < 0.0 && moved.y > motion.y;
+        for axis in 0..3 {
+            if moved[axis] != motion[axis] {
+                self.velocity[axis] = 0.0;
+            }
+        }
+        // Stepping up moves the body upwards without it having any upwards velocity.
+        if moved.y > motion.y.max(0.0) {
+            self.on_ground = true;
+            self.velocity.y = 0.0;
+        }
+
+        self.position += moved;
+    }
+}
@@ -0,0 +1,72 @@
+use glam::{IVec3, Vec3};
+
+use crate::{tile::TileId, world::World};
+
+/// The tile hit by [`World::raycast`].
+#[derive(Copy, Clone, Debug)]
+pub struct RaycastHit {
+    pub pos: IVec3,
+    pub tile: TileId,
+    /// The normal of the face the ray entered through, or zero if the ray started inside the tile.
+    pub normal: IVec3,
+    /// How far along the ray the tile was hit.
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    /// The position next to the face that was hit, where a tile would be placed.
+    pub fn adjacent(&self) -> IVec3 {
+        self.pos + self.normal
+    }
+}
+
+impl World {
+    /// Finds the first tile along a ray, stepping through every tile the ray touches in order.
+    /// Tiles in unloaded chunks count as air.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        let direction = direction.try_normalize()?;
+        let mut pos = origin.floor().as_ivec3();
+
+        let step = direction.signum().as_ivec3();
+        // How far along the ray it takes to cross a whole tile on each axis.
+        let delta = direction.recip().abs();
+        // How far along the ray the next tile border on each axis is.
+        let mut next = Vec3::from_array(std::array::from_fn(|axis| {
+            let border = match step[axis] > 0 {
+                true => pos[axis] as f32 + 1.0,
+                false => pos[axis] as f32,
+            };
+            match direction[axis] {
+                0.0 => f32::INFINITY,
+                d => (border - origin[axis]) / d,
+            }
+        }));
+
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+        while distance <= max_distance {
+            if let Some(tile) = self.get_tile(pos) {
+                return Some(RaycastHit {
+                    pos,
+                    tile,
+                    normal,
+                    distance,
+                });
+            }
+
+            let axis = match (next.x < next.y, next.x < next.z, next.y < next.z) {
+                (true, true, _) => 0,
+                (false, _, true) => 1,
+                _ => 2,
+            };
+            distance = next[axis];
+            next[axis] += delta[axis];
+            pos[axis] += step[axis];
+
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+
+        None
+    }
+}
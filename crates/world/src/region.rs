@@ -0,0 +1,634 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::{Chunk, CHUNK_VOLUME},
+    gen::PendingTile,
+    palette::PalettedStorage,
+    save::{SaveError, FORMAT_VERSION},
+    tile::{TileId, TileRegistry, TileState},
+};
+
+/// The number of chunks along each side of a region.
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"TRRG";
+const HEADER_LEN: u64 = 4 + REGION_VOLUME as u64 * 8;
+
+/// A file holding the compressed data of up to [`REGION_VOLUME`] chunks.
+///
+/// The file starts with a table of `(offset, length)` pairs, one per chunk, followed by the chunk
+/// data. A length of zero means the chunk has not been saved.
+pub struct Region {
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl Region {
+    /// Returns the region holding a chunk, and the chunk's index inside that region.
+    pub fn locate(chunk: IVec3) -> (IVec3, usize) {
+        let region = chunk.div_euclid(IVec3::splat(REGION_SIZE));
+        let local = chunk.rem_euclid(IVec3::splat(REGION_SIZE));
+        let index = local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE;
+        (region, index as usize)
+    }
+
+    pub fn path(dir: &Path, region: IVec3) -> PathBuf {
+        dir.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    /// Reads every chunk in a region file. A missing file is an empty region.
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    chunks: vec![None; REGION_VOLUME],
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let header = read_header(&mut file, path)?;
+        let mut chunks = vec![None; REGION_VOLUME];
+        for (i, (offset, len)) in header.into_iter().enumerate() {
+            if len != 0 {
+                chunks[i] = Some(read_blob(&mut file, offset, len)?);
+            }
+        }
+
+        Ok(Self { chunks })
+    }
+
+    /// Reads a single chunk's compressed data without loading the rest of the region.
+    pub fn read_chunk(path: &Path, index: usize) -> Result<Option<Vec<u8>>, SaveError> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let (offset, len) = read_header(&mut file, path)?[index];
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(read_blob(&mut file, offset, len)?))
+    }
+
+    pub fn set(&mut self, index: usize, data: Vec<u8>) {
+        self.chunks[index] = Some(data);
+    }
+
+    /// Writes the region to a temporary file first, so a crash never leaves a half written region.
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(MAGIC);
+
+        let mut offset = HEADER_LEN as u32;
+        for chunk in &self.chunks {
+            let len = chunk.as_ref().map_or(0, |c| c.len() as u32);
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&len.to_le_bytes());
+            offset += len;
+        }
+
+        let tmp = path.with_extension("region.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&header)?;
+        for chunk in self.chunks.iter().flatten() {
+            file.write_all(chunk)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+}
+
+/// Tiles generated into the chunks of a region before those chunks existed, as written in a toml
+/// file next to the region.
+#[derive(Serialize, Deserialize, Default)]
+struct PendingFile {
+    tile: Vec<SavedTile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTile {
+    pos: [i32; 3],
+    tile: String,
+}
+
+pub fn pending_path(dir: &Path, region: IVec3) -> PathBuf {
+    dir.join(format!(
+        "r.{}.{}.{}.pending.toml",
+        region.x, region.y, region.z
+    ))
+}
+
+/// Reads the tiles waiting for the chunks of a region. Tiles which are no longer registered are
+/// dropped.
+pub fn read_pending(
+    dir: &Path,
+    region: IVec3,
+    registry: &TileRegistry,
+) -> Result<Vec<PendingTile>, SaveError> {
+    let src = match fs::read_to_string(pending_path(dir, region)) {
+        Ok(src) => src,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let file: PendingFile = toml::from_str(&src)?;
+    Ok(file
+        .tile
+        .into_iter()
+        .filter_map(|saved| {
+            let tile = registry.id(&saved.tile);
+            if tile.is_none() {
+                log::warn!(
+                    "Pending tile `{}` is not registered, dropping it",
+                    saved.tile
+                );
+            }
+            Some(PendingTile {
+                pos: IVec3::from(saved.pos),
+                tile: tile?,
+            })
+        })
+        .collect())
+}
+
+/// Writes the tiles waiting for the chunks of a region, removing the file once there are none.
+pub fn write_pending(
+    dir: &Path,
+    region: IVec3,
+    tiles: &[PendingTile],
+    registry: &TileRegistry,
+) -> Result<(), SaveError> {
+    let path = pending_path(dir, region);
+    if tiles.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    let file = PendingFile {
+        tile: tiles
+            .iter()
+            .map(|tile| SavedTile {
+                pos: tile.pos.to_array(),
+                tile: registry.get(tile.tile).name.clone(),
+            })
+            .collect(),
+    };
+    fs::write(path, toml::to_string(&file)?)?;
+    Ok(())
+}
+
+fn read_header(file: &mut File, path: &Path) -> Result<Vec<(u32, u32)>, SaveError> {
+    let mut header = vec![0; HEADER_LEN as usize];
+    file.read_exact(&mut header)
+        .map_err(|_| SaveError::CorruptRegion(path.to_path_buf()))?;
+    if &header[0..4] != MAGIC {
+        return Err(SaveError::CorruptRegion(path.to_path_buf()));
+    }
+
+    Ok(header[4..]
+        .chunks_exact(8)
+        .map(|entry| {
+            (
+                u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+fn read_blob(file: &mut File, offset: u32, len: u32) -> Result<Vec<u8>, SaveError> {
+    let mut data = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Reads a single saved chunk from the region files in `dir`, returning `None` if it was never saved.
+pub fn load_chunk(
+    dir: &Path,
+    pos: IVec3,
+    registry: &TileRegistry,
+) -> Result<Option<Chunk>, SaveError> {
+    let (region, index) = Region::locate(pos);
+    match Region::read_chunk(&Region::path(dir, region), index)? {
+        Some(data) => Ok(Some(decode_chunk(&data, FORMAT_VERSION, registry)?)),
+        None => Ok(None),
+    }
+}
+
+/// Rewrites every region in `dir` from an older `version` of the format into the current one.
+pub fn upgrade(dir: &Path, version: u32, registry: &TileRegistry) -> Result<(), SaveError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "region") {
+            continue;
+        }
+
+        let mut region = Region::read(&path)?;
+        for data in region.chunks.iter_mut().flatten() {
+            *data = encode_chunk(&decode_chunk(data, version, registry)?, registry)?;
+        }
+        region.write(&path)?;
+    }
+    Ok(())
+}
+
+/// Serializes and compresses a chunk.
+/// Tiles are stored by name, so saves keep working when tiles are added to the registry.
+pub fn encode_chunk(chunk: &Chunk, registry: &TileRegistry) -> Result<Vec<u8>, SaveError> {
+    let storage = chunk.storage();
+    let mut out = ZlibEncoder::new(vec![], Compression::default());
+
+    out.write_all(&(storage.palette().len() as u32).to_le_bytes())?;
+    for tile in storage.palette() {
+        // Air is saved as an empty name.
+        write_str(&mut out, tile.map_or("", |t| registry.get(t).name.as_str()))?;
+    }
+
+    write_indices(&mut out, storage)?;
+
+    // Fluid levels follow the tiles, since version 2.
+    let fluid = chunk.fluid_storage();
+    out.write_all(&(fluid.palette().len() as u32).to_le_bytes())?;
+    out.write_all(fluid.palette())?;
+    write_indices(&mut out, fluid)?;
+
+    // Tile states follow the fluid levels, since version 3 as the tile they belong to and the value
+    // of each of its properties, so they keep their meaning when properties are added or reordered.
+    let states = chunk.state_storage();
+    let mut tile_states = PalettedStorage::new(CHUNK_VOLUME, (None, TileState::default()));
+    for i in 0..CHUNK_VOLUME {
+        let state = states.get(i);
+        if state != TileState::default() {
+            tile_states.set(i, (storage.get(i), state));
+        }
+    }
+
+    out.write_all(&(tile_states.palette().len() as u32).to_le_bytes())?;
+    for (tile, state) in tile_states.palette() {
+        let values = tile.map_or(vec![], |t| {
+            let def = registry.get(t);
+            def.properties
+                .iter()
+                .filter_map(|p| Some((p.name.as_str(), def.property(*state, &p.name)?)))
+                .collect()
+        });
+        write_str(&mut out, tile.map_or("", |t| registry.get(t).name.as_str()))?;
+        out.write_all(&(values.len() as u16).to_le_bytes())?;
+        for (name, value) in values {
+            write_str(&mut out, name)?;
+            write_str(&mut out, value)?;
+        }
+    }
+    write_indices(&mut out, &tile_states)?;
+
+    Ok(out.finish()?)
+}
+
+fn write_str(out: &mut impl Write, s: &str) -> Result<(), SaveError> {
+    out.write_all(&(s.len() as u16).to_le_bytes())?;
+    out.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn write_indices<T: Copy + PartialEq>(
+    out: &mut impl Write,
+    storage: &PalettedStorage<T>,
+) -> Result<(), SaveError> {
+    out.write_all(&[storage.bits_per_value() as u8])?;
+    out.write_all(&(storage.raw_data().len() as u32).to_le_bytes())?;
+    for word in storage.raw_data() {
+        out.write_all(&word.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Decompresses and deserializes a chunk written by [`encode_chunk`] in a `version` of the format.
+/// Tiles which are no longer registered are loaded as air.
+pub fn decode_chunk(
+    data: &[u8],
+    version: u32,
+    registry: &TileRegistry,
+) -> Result<Chunk, SaveError> {
+    let mut bytes = vec![];
+    ZlibDecoder::new(data)
+        .read_to_end(&mut bytes)
+        .map_err(|_| SaveError::CorruptChunk)?;
+    let mut reader = Reader { bytes: &bytes };
+
+    let palette_len = reader.u32()?;
+    let mut palette: Vec<Option<TileId>> = vec![];
+    for _ in 0..palette_len {
+        let name = reader.str()?;
+        if name.is_empty() {
+            palette.push(None);
+            continue;
+        }
+
+        let id = registry.id(name);
+        if id.is_none() {
+            log::warn!("Saved tile `{name}` is not registered, loading it as air");
+        }
+        palette.push(id);
+    }
+
+    let storage = reader.storage(palette)?;
+    let mut chunk = Chunk::from_storage(storage);
+
+    if version < 2 {
+        return Ok(chunk);
+    }
+
+    let palette_len = reader.u32()? as usize;
+    let palette = reader.take(palette_len)?.to_vec();
+    chunk.set_fluid_storage(reader.storage(palette)?);
+
+    let palette_len = reader.u32()? as usize;
+    let palette = match version {
+        // States were saved as raw indices into the combinations of their tile's properties.
+        2 => reader
+            .take(palette_len * 2)?
+            .chunks_exact(2)
+            .map(|b| TileState(u16::from_le_bytes([b[0], b[1]])))
+            .collect(),
+        _ => (0..palette_len)
+            .map(|_| reader.tile_state(registry))
+            .collect::<Result<_, _>>()?,
+    };
+    chunk.set_state_storage(reader.storage(palette)?);
+    Ok(chunk)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveError> {
+        if self.bytes.len() < len {
+            return Err(SaveError::CorruptChunk);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, SaveError> {
+        let len = self.u16()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| SaveError::CorruptChunk)
+    }
+
+    /// Reads a state written by [`encode_chunk`] as its tile and the values of its properties.
+    /// Properties and values the tile no longer has are left at their defaults.
+    fn tile_state(&mut self, registry: &TileRegistry) -> Result<TileState, SaveError> {
+        let def = registry.id(self.str()?).map(|id| registry.get(id));
+        let mut state = TileState::default();
+        for _ in 0..self.u16()? {
+            let (name, value) = (self.str()?, self.str()?);
+            let Some(def) = def else {
+                continue;
+            };
+            match def.with_property(state, name, value) {
+                Some(changed) => state = changed,
+                None => log::warn!(
+                    "Saved property `{name}={value}` of tile `{}` is not registered, loading its default",
+                    def.name
+                ),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Reads the indices written by `write_indices`, into a palette read beforehand.
+    fn storage<T: Copy + PartialEq>(
+        &mut self,
+        palette: Vec<T>,
+    ) -> Result<PalettedStorage<T>, SaveError> {
+        let bits = self.take(1)?[0] as u32;
+        let words = self.u32()?;
+        let mut data = vec![];
+        for _ in 0..words {
+            data.push(u64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+        }
+
+        PalettedStorage::from_raw(CHUNK_VOLUME, palette, bits, data).ok_or(SaveError::CorruptChunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::CHUNK_SIZE;
+
+    const TILES: &str = r#"
+[[tile]]
+name = "stone"
+
+[[tile]]
+name = "water"
+solid = false
+fluid = {}
+
+[[tile]]
+name = "log"
+properties = [{ name = "axis", values = ["y", "x", "z"] }]
+"#;
+
+    /// A chunk with a bit of everything a chunk saves: tiles, a flowing fluid and a tile state.
+    fn chunk(registry: &TileRegistry) -> Chunk {
+        let [stone, water, log] = ["stone", "water", "log"].map(|n| registry.id(n));
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z], stone);
+            }
+        }
+        chunk.set([3, 1, 4], water);
+        chunk.set_fluid([3, 1, 4], 5);
+        chunk.set([7, 1, 2], log);
+        let def = registry.get(log.unwrap());
+        chunk.set_state(
+            [7, 1, 2],
+            def.with_property(TileState::default(), "axis", "x")
+                .unwrap(),
+        );
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let loc = [x, y, z];
+                    assert_eq!(a.get(loc), b.get(loc), "tile at {loc:?}");
+                    assert_eq!(a.fluid(loc), b.fluid(loc), "fluid at {loc:?}");
+                    assert_eq!(a.state(loc), b.state(loc), "state at {loc:?}");
+                }
+            }
+        }
+    }
+
+    /// An empty directory for a test to save into.
+    fn save_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("world-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Encodes a chunk the way an older `version` of the format did.
+    fn encode_old(chunk: &Chunk, version: u32, registry: &TileRegistry) -> Vec<u8> {
+        let storage = chunk.storage();
+        let mut out = ZlibEncoder::new(vec![], Compression::default());
+        out.write_all(&(storage.palette().len() as u32).to_le_bytes())
+            .unwrap();
+        for tile in storage.palette() {
+            write_str(&mut out, tile.map_or("", |t| registry.get(t).name.as_str())).unwrap();
+        }
+        write_indices(&mut out, storage).unwrap();
+
+        if version >= 2 {
+            let fluid = chunk.fluid_storage();
+            out.write_all(&(fluid.palette().len() as u32).to_le_bytes())
+                .unwrap();
+            out.write_all(fluid.palette()).unwrap();
+            write_indices(&mut out, fluid).unwrap();
+
+            let states = chunk.state_storage();
+            out.write_all(&(states.palette().len() as u32).to_le_bytes())
+                .unwrap();
+            for state in states.palette() {
+                out.write_all(&state.0.to_le_bytes()).unwrap();
+            }
+            write_indices(&mut out, states).unwrap();
+        }
+        out.finish().unwrap()
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let chunk = chunk(&registry);
+        let data = encode_chunk(&chunk, &registry).unwrap();
+        assert_same(
+            &decode_chunk(&data, FORMAT_VERSION, &registry).unwrap(),
+            &chunk,
+        );
+
+        // Empty chunks still save their single palette entry.
+        let data = encode_chunk(&Chunk::new(), &registry).unwrap();
+        assert_same(
+            &decode_chunk(&data, FORMAT_VERSION, &registry).unwrap(),
+            &Chunk::new(),
+        );
+    }
+
+    #[test]
+    fn states_survive_reordered_properties() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let data = encode_chunk(&chunk(&registry), &registry).unwrap();
+
+        let reordered =
+            TileRegistry::from_toml(&TILES.replace(r#"["y", "x", "z"]"#, r#"["x", "z", "y"]"#))
+                .unwrap();
+        let chunk = decode_chunk(&data, FORMAT_VERSION, &reordered).unwrap();
+        let log = reordered.get(reordered.id("log").unwrap());
+        assert_eq!(log.property(chunk.state([7, 1, 2]), "axis"), Some("x"));
+    }
+
+    #[test]
+    fn corrupt_chunks_are_errors() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        assert!(matches!(
+            decode_chunk(b"not a chunk", FORMAT_VERSION, &registry),
+            Err(SaveError::CorruptChunk)
+        ));
+
+        // Dropping the state section leaves the chunk cut short.
+        let data = encode_old(&chunk(&registry), 1, &registry);
+        assert!(matches!(
+            decode_chunk(&data, FORMAT_VERSION, &registry),
+            Err(SaveError::CorruptChunk)
+        ));
+    }
+
+    #[test]
+    fn regions_round_trip() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        let dir = save_dir("regions");
+        let pos = IVec3::new(-1, 2, 9);
+        assert!(load_chunk(&dir, pos, &registry).unwrap().is_none());
+
+        let (region, index) = Region::locate(pos);
+        let path = Region::path(&dir, region);
+        let mut file = Region::read(&path).unwrap();
+        file.set(index, encode_chunk(&chunk(&registry), &registry).unwrap());
+        file.write(&path).unwrap();
+
+        assert_same(
+            &load_chunk(&dir, pos, &registry).unwrap().unwrap(),
+            &chunk(&registry),
+        );
+        assert!(load_chunk(&dir, pos + IVec3::X, &registry)
+            .unwrap()
+            .is_none());
+
+        fs::write(&path, b"TRRG").unwrap();
+        assert!(matches!(
+            load_chunk(&dir, pos, &registry),
+            Err(SaveError::CorruptRegion(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn upgrade_from_each_version() {
+        let registry = TileRegistry::from_toml(TILES).unwrap();
+        for version in 1..FORMAT_VERSION {
+            let dir = save_dir(&format!("upgrade-{version}"));
+            let chunk = chunk(&registry);
+            let path = Region::path(&dir, IVec3::ZERO);
+            let mut region = Region::read(&path).unwrap();
+            region.set(0, encode_old(&chunk, version, &registry));
+            region.write(&path).unwrap();
+
+            upgrade(&dir, version, &registry).unwrap();
+            let upgraded = load_chunk(&dir, IVec3::ZERO, &registry).unwrap().unwrap();
+            match version {
+                // Fluid levels and states weren't saved yet, so they load as their defaults.
+                1 => {
+                    let mut tiles_only = chunk.clone();
+                    tiles_only.set_fluid([3, 1, 4], 0);
+                    tiles_only.set_state([7, 1, 2], TileState::default());
+                    assert_same(&upgraded, &tiles_only);
+                }
+                _ => assert_same(&upgraded, &chunk),
+            }
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
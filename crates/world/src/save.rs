@@ -0,0 +1,119 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The current version of the on-disk world format.
+///
+/// 1. Chunks hold their tiles.
+/// 2. Fluid levels and tile states follow the tiles.
+/// 3. Tile states are saved as the values of their tile's properties.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The name of the metadata file inside a world directory.
+pub const META_FILE: &str = "world.toml";
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
+    #[error(transparent)]
+    TomlSerializeError(#[from] toml::ser::Error),
+
+    #[error(
+        "World format version {0} is not supported, expected at most version {FORMAT_VERSION}"
+    )]
+    UnsupportedVersion(u32),
+
+    #[error("Region file {0:?} is corrupt")]
+    CorruptRegion(PathBuf),
+
+    #[error("Chunk data is corrupt")]
+    CorruptChunk,
+
+    #[error(
+        "World has no save directory, open or create it with `World::open` or `World::create`"
+    )]
+    NoSaveDirectory,
+}
+
+/// The contents of a world's metadata file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldMeta {
+    pub version: u32,
+    pub seed: u64,
+}
+
+impl WorldMeta {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            seed,
+        }
+    }
+
+    /// Reads the metadata of a world saved in this or an older version of the format.
+    pub fn read(dir: &Path) -> Result<Self, SaveError> {
+        let meta: Self = toml::from_str(&fs::read_to_string(dir.join(META_FILE))?)?;
+        if meta.version == 0 || meta.version > FORMAT_VERSION {
+            return Err(SaveError::UnsupportedVersion(meta.version));
+        }
+        Ok(meta)
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), SaveError> {
+        fs::write(dir.join(META_FILE), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("world-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn meta_round_trips() {
+        let dir = save_dir("meta");
+        WorldMeta::new(42).write(&dir).unwrap();
+        let meta = WorldMeta::read(&dir).unwrap();
+        assert_eq!((meta.version, meta.seed), (FORMAT_VERSION, 42));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_older_versions_only() {
+        let dir = save_dir("versions");
+        for version in 1..=FORMAT_VERSION {
+            WorldMeta { version, seed: 7 }.write(&dir).unwrap();
+            assert_eq!(WorldMeta::read(&dir).unwrap().version, version);
+        }
+        for version in [0, FORMAT_VERSION + 1] {
+            WorldMeta { version, seed: 7 }.write(&dir).unwrap();
+            assert!(matches!(
+                WorldMeta::read(&dir),
+                Err(SaveError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+
+        fs::write(dir.join(META_FILE), "seed = ").unwrap();
+        assert!(matches!(
+            WorldMeta::read(&dir),
+            Err(SaveError::TomlError(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
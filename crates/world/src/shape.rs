@@ -0,0 +1,313 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use glam::{IVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use render::mesh::builder::MeshBuilder;
+
+use crate::{
+    chunk::{Chunk, ChunkNeighbors, CHUNK_SIZE, FACE_OFFSETS},
+    light,
+    mesher::vertex_ao,
+    physics::Aabb,
+    tile::{TileDef, TileRegistry, TileState},
+    world::World,
+};
+
+/// How far apart two coordinates of a model can be while still counting as the same.
+const EPSILON: f32 = 1e-4;
+
+/// The sides a fence connects towards, each a property of the fence set to `"true"` or `"false"`.
+/// They are named after the faces of [`FACE_OFFSETS`].
+pub const CONNECTIONS: [(usize, &str); 4] = [(2, "left"), (3, "right"), (4, "front"), (5, "back")];
+
+/// The shape a tile is drawn and collides with, before being rotated by its variants.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileShape {
+    #[default]
+    Cube,
+    /// The bottom half of a tile.
+    Slab,
+    /// A slab with a step on its back half.
+    Stairs,
+    /// A post with an arm towards each side named in [`CONNECTIONS`] that is set to `"true"`.
+    Fence,
+    /// Two quads crossing diagonally, like a plant. Nothing collides with it.
+    Cross,
+    /// Boxes given by their min and max corners, from 0 to 1 across the tile.
+    Boxes(Vec<[[f32; 3]; 2]>),
+}
+
+impl TileShape {
+    /// The boxes of the shape in a state, before rotation.
+    fn boxes(&self, def: &TileDef, state: TileState) -> Vec<Aabb> {
+        let aabb = |min: [f32; 3], max: [f32; 3]| Aabb::new(min.into(), max.into());
+        match self {
+            Self::Cube => vec![aabb([0.0; 3], [1.0; 3])],
+            Self::Slab => vec![aabb([0.0; 3], [1.0, 0.5, 1.0])],
+            Self::Stairs => vec![
+                aabb([0.0; 3], [1.0, 0.5, 1.0]),
+                aabb([0.0, 0.5, 0.5], [1.0; 3]),
+            ],
+            Self::Fence => {
+                let mut boxes = vec![aabb([0.375, 0.0, 0.375], [0.625, 1.0, 0.625])];
+                for (face, name) in CONNECTIONS {
+                    if def.property(state, name) != Some("true") {
+                        continue;
+                    }
+                    // An arm runs from the post out to the side, along the face's axis.
+                    let mut min = [0.4375, 0.375, 0.4375];
+                    let mut max = [0.5625, 0.9375, 0.5625];
+                    let axis = FACE_OFFSETS[face].iter().position(|v| *v != 0).unwrap();
+                    (min[axis], max[axis]) = match FACE_OFFSETS[face][axis] > 0 {
+                        true => (0.625, 1.0),
+                        false => (0.0, 0.375),
+                    };
+                    boxes.push(aabb(min, max));
+                }
+                boxes
+            }
+            Self::Cross => vec![],
+            Self::Boxes(boxes) => boxes.iter().map(|[min, max]| aabb(*min, *max)).collect(),
+        }
+    }
+
+    /// Whether every box lies inside the tile.
+    pub(crate) fn fits(&self) -> bool {
+        let Self::Boxes(boxes) = self else {
+            return true;
+        };
+        boxes.iter().all(|[min, max]| {
+            (0..3).all(|axis| 0.0 <= min[axis] && min[axis] < max[axis] && max[axis] <= 1.0)
+        })
+    }
+}
+
+/// A face of a box in a [`TileModel`].
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ModelFace {
+    pub aabb: Aabb,
+    pub face: usize,
+    /// The face as `[min_u, min_v, max_u, max_v]`, over the two axes it lies on in the same order
+    /// as the greedy mesher.
+    pub rect: [f32; 4],
+    /// Whether the face lies on the side of the tile, where a neighbor can hide it.
+    pub on_side: bool,
+}
+
+/// The boxes and quads a tile is drawn with in one state, rotated by its variant, from 0 to 1
+/// across the tile.
+#[derive(Clone, Debug)]
+pub struct TileModel {
+    /// The boxes the tile is drawn with, which are also what collides with it.
+    pub boxes: Vec<Aabb>,
+    /// Whether the tile is drawn as crossed quads.
+    pub cross: bool,
+
+    /// The faces of the boxes, leaving out those hidden against another box of the model.
+    faces: Vec<ModelFace>,
+    /// The parts of each side of the tile covered by a face, in the same form as [`ModelFace::rect`].
+    sides: [Vec<[f32; 4]>; 6],
+}
+
+impl TileModel {
+    pub fn new(def: &TileDef, state: TileState) -> Self {
+        let variant = def.variant(state);
+        let boxes: Vec<Aabb> = def
+            .shape
+            .boxes(def, state)
+            .into_iter()
+            .map(|aabb| match variant {
+                Some(variant) => {
+                    let a = Vec3::from(variant.rotate_point(aabb.min.into()));
+                    let b = Vec3::from(variant.rotate_point(aabb.max.into()));
+                    Aabb::new(a.min(b), a.max(b))
+                }
+                None => aabb,
+            })
+            .collect();
+
+        let mut all = vec![];
+        for aabb in &boxes {
+            for (face, dir) in FACE_OFFSETS.into_iter().enumerate() {
+                let axis = dir.iter().position(|v| *v != 0).unwrap();
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let plane = match dir[axis] > 0 {
+                    true => aabb.max[axis],
+                    false => aabb.min[axis],
+                };
+                all.push((
+                    plane,
+                    ModelFace {
+                        aabb: *aabb,
+                        face,
+                        rect: [aabb.min[u], aabb.min[v], aabb.max[u], aabb.max[v]],
+                        on_side: (plane - (dir[axis] > 0) as u8 as f32).abs() < EPSILON,
+                    },
+                ));
+            }
+        }
+
+        // Faces pressed against a face of another box pointing the other way can't be seen.
+        let faces: Vec<ModelFace> = all
+            .iter()
+            .filter(|(plane, face)| {
+                !all.iter().any(|(other_plane, other)| {
+                    other.face == opposite(face.face)
+                        && (plane - other_plane).abs() < EPSILON
+                        && contains(other.rect, face.rect)
+                })
+            })
+            .map(|(_, face)| *face)
+            .collect();
+
+        let mut sides: [Vec<[f32; 4]>; 6] = Default::default();
+        for face in faces.iter().filter(|face| face.on_side) {
+            sides[face.face].push(face.rect);
+        }
+
+        Self {
+            boxes,
+            cross: def.shape == TileShape::Cross,
+            faces,
+            sides,
+        }
+    }
+
+    /// Whether the side of the tile facing `face` is covered over all of `rect`.
+    pub(crate) fn covers(&self, face: usize, rect: [f32; 4]) -> bool {
+        self.sides[face].iter().any(|side| contains(*side, rect))
+    }
+}
+
+/// The face pointing the other way.
+pub(crate) fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+fn contains(outer: [f32; 4], inner: [f32; 4]) -> bool {
+    outer[0] <= inner[0] + EPSILON
+        && outer[1] <= inner[1] + EPSILON
+        && outer[2] >= inner[2] - EPSILON
+        && outer[3] >= inner[3] - EPSILON
+}
+
+impl World {
+    /// Connects the fences at and beside `pos` to their neighbors, after the tile there changed.
+    /// Fences join other fences and opaque cubes.
+    pub fn update_connections(&mut self, pos: IVec3, registry: &TileRegistry) {
+        let beside = CONNECTIONS.map(|(face, _)| pos + IVec3::from(FACE_OFFSETS[face]));
+        for pos in [pos].into_iter().chain(beside) {
+            let Some(tile) = self.get_tile(pos) else {
+                continue;
+            };
+            let def = registry.get(tile);
+            if def.shape != TileShape::Fence {
+                continue;
+            }
+
+            let current = self.get_state(pos);
+            let mut state = current;
+            for (face, name) in CONNECTIONS {
+                let connects = self
+                    .get_tile(pos + IVec3::from(FACE_OFFSETS[face]))
+                    .is_some_and(|other| {
+                        let other = registry.get(other);
+                        other.shape == TileShape::Fence || other.occludes()
+                    });
+                let value = if connects { "true" } else { "false" };
+                state = def.with_property(state, name, value).unwrap_or(state);
+            }
+            if state != current {
+                self.set_state(pos, state);
+            }
+        }
+    }
+}
+
+/// Meshes the tiles of a chunk which aren't cubes, which the regular meshers skip.
+///
+/// Faces on the side of a tile are culled wherever the neighbor covers them, while faces inside the
+/// tile always show and are lit by the tile itself.
+pub(crate) fn mesh(
+    chunk: &Chunk,
+    registry: &TileRegistry,
+    neighbors: &ChunkNeighbors,
+    builder: &mut MeshBuilder,
+    offset: [f32; 3],
+) {
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let loc = [x, y, z];
+                let Some(tile) = chunk.get(loc) else {
+                    continue;
+                };
+                let def = registry.get(tile);
+                if def.shape == TileShape::Cube || def.fluid.is_some() {
+                    continue;
+                }
+
+                let state = chunk.state(loc);
+                let model = registry.model(tile, state);
+                let coord = [0, 1, 2].map(|i| offset[i] + loc[i] as f32);
+                let own_light = light::vertex_light(chunk.packed_light(loc));
+
+                for face in &model.faces {
+                    let light = match face.on_side {
+                        true => {
+                            if !chunk
+                                .side_visible(registry, neighbors, tile, loc, face.face, face.rect)
+                            {
+                                continue;
+                            }
+                            light::vertex_light(chunk.face_light(neighbors, loc, face.face))
+                        }
+                        false => own_light,
+                    };
+
+                    builder.add_box_face(
+                        coord,
+                        face.aabb.min.to_array(),
+                        face.aabb.max.to_array(),
+                        face.face,
+                        registry.texture_layer(tile, state, face.face),
+                        light,
+                        vertex_ao([3; 4]),
+                    );
+                }
+
+                if model.cross {
+                    let layer = registry.texture_layer(tile, state, 4);
+                    for (corners, normal) in cross_quads() {
+                        builder.add_quad(
+                            coord,
+                            corners,
+                            [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0]],
+                            normal,
+                            layer,
+                            own_light,
+                            vertex_ao([3; 4]),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The corners and normals of the quads of [`TileShape::Cross`], both sides of both diagonals.
+fn cross_quads() -> [([[f32; 3]; 4], [f32; 3]); 4] {
+    [
+        ([0.0, 0.0], [1.0, 1.0]),
+        ([1.0, 1.0], [0.0, 0.0]),
+        ([0.0, 1.0], [1.0, 0.0]),
+        ([1.0, 0.0], [0.0, 1.0]),
+    ]
+    .map(|([ax, az], [bx, bz])| {
+        let corners = [[ax, 0.0, az], [ax, 1.0, az], [bx, 0.0, bz], [bx, 1.0, bz]];
+        let normal = [(bz - az) * FRAC_1_SQRT_2, 0.0, (ax - bx) * FRAC_1_SQRT_2];
+        (corners, normal)
+    })
+}
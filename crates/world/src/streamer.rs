@@ -0,0 +1,594 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use glam::{IVec3, Vec3};
+use wgpu::naga::{FastHashMap, FastHashSet};
+
+use render::{frustum::Frustum, mesh::builder::MeshBuilder};
+
+use crate::{
+    chunk::{neighbor_index, neighbor_offset, Chunk, ChunkNeighbors, FACE_OFFSETS, NEIGHBORHOOD},
+    gen::{PendingTile, WorldGenerator},
+    light, lod,
+    mesher::MeshMode,
+    region,
+    save::SaveError,
+    tile::TileRegistry,
+    visibility::{self, VisibilityGraph},
+    world::World,
+};
+
+/// Meshes which finished building, and chunks which were unloaded, since the last update.
+#[derive(Default)]
+pub struct StreamUpdate {
+    /// Finished meshes, ready to be uploaded with [`MeshBuilder::build`].
+    pub meshed: Vec<(IVec3, MeshBuilder)>,
+    /// Chunks which were unloaded, whose meshes should be dropped.
+    pub unloaded: Vec<IVec3>,
+}
+
+/// Loads, generates and meshes chunks on worker threads in a radius around the camera, unloading
+/// them again as the camera moves away. Chunks nearest the camera are always worked on first.
+///
+/// The main thread only inserts finished chunks into the [`World`] and uploads finished meshes.
+/// Chunks which are edited after being meshed, see [`World::take_dirty`], are instead remeshed on the
+/// main thread so edits show up the same frame, spending at most [`Self::remesh_budget`] each update.
+pub struct ChunkStreamer {
+    /// How many chunks are loaded horizontally around the camera.
+    pub radius: i32,
+    /// How many chunks are loaded above and below the camera.
+    pub vertical_radius: i32,
+    /// How long each update may spend remeshing edited chunks. At least one chunk is always remeshed.
+    pub remesh_budget: Duration,
+    /// How many chunks away from the camera chunks start being drawn at a lower level of detail,
+    /// see [`lod::lod_level`]. Everything is drawn at full detail if this is `None`.
+    pub lod_distance: Option<f32>,
+
+    mesh_mode: MeshMode,
+    center: Option<IVec3>,
+
+    /// Chunks which have been sent to a worker to be loaded or generated.
+    generating: FastHashSet<IVec3>,
+    /// Chunks whose mesh is out of date, which are sent to the workers.
+    dirty: FastHashSet<IVec3>,
+    /// Meshed chunks which were edited, which are remeshed on the main thread.
+    remesh: FastHashSet<IVec3>,
+    /// The latest mesh version requested for each chunk, so outdated meshes can be dropped.
+    versions: FastHashMap<IVec3, u64>,
+    /// The level of detail each chunk was last meshed at.
+    levels: FastHashMap<IVec3, u8>,
+    /// Which faces of each meshed chunk see each other, from when it was last meshed.
+    graphs: FastHashMap<IVec3, VisibilityGraph>,
+    next_version: u64,
+
+    pool: WorkerPool,
+}
+
+impl ChunkStreamer {
+    /// Starts the worker threads, which load chunks from the world's save and generate them with its
+    /// generator.
+    pub fn new(
+        world: &World,
+        registry: Arc<TileRegistry>,
+        radius: i32,
+        vertical_radius: i32,
+    ) -> Self {
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get().saturating_sub(1))
+            .max(1);
+
+        Self {
+            radius,
+            vertical_radius,
+            remesh_budget: Duration::from_millis(4),
+            lod_distance: None,
+
+            mesh_mode: MeshMode::default(),
+            center: None,
+
+            generating: FastHashSet::default(),
+            dirty: FastHashSet::default(),
+            remesh: FastHashSet::default(),
+            versions: FastHashMap::default(),
+            levels: FastHashMap::default(),
+            graphs: FastHashMap::default(),
+            next_version: 0,
+
+            pool: WorkerPool::new(
+                threads,
+                WorkerContext {
+                    registry,
+                    generator: world.generator().cloned(),
+                    save_dir: world.save_dir().map(|d| d.to_path_buf()),
+                },
+            ),
+        }
+    }
+
+    pub fn mesh_mode(&self) -> MeshMode {
+        self.mesh_mode
+    }
+
+    /// Sets the mesh mode of every loaded chunk and every chunk loaded later, remeshing them all.
+    pub fn set_mesh_mode(&mut self, world: &mut World, mode: MeshMode) {
+        self.mesh_mode = mode;
+        for (_, chunk) in world.chunks_mut() {
+            chunk.set_mesh_mode(mode);
+        }
+        self.remesh_all(world);
+    }
+
+    /// Queues a loaded chunk to be remeshed.
+    pub fn mark_dirty(&mut self, pos: IVec3) {
+        self.dirty.insert(pos);
+    }
+
+    /// Meshes a loaded chunk on the calling thread, for edits which should show up immediately.
+    /// Any mesh of the chunk still being built by a worker is discarded.
+    pub fn mesh_now(&mut self, world: &World, pos: IVec3) -> Option<MeshBuilder> {
+        let chunk = world.chunk(pos)?;
+        self.dirty.remove(&pos);
+        self.remesh.remove(&pos);
+        self.next_version += 1;
+        self.versions.insert(pos, self.next_version);
+
+        let level = self.lod(pos);
+        self.levels.insert(pos, level);
+        let registry = &self.pool.context.registry;
+        self.graphs
+            .insert(pos, VisibilityGraph::new(chunk, registry));
+        Some(chunk.build_lod_mesh(
+            registry,
+            &world.neighbors(pos).with_lods(self.neighbor_lods(pos)),
+            level,
+            World::chunk_origin(pos).as_vec3().to_array(),
+        ))
+    }
+
+    pub fn remesh_all(&mut self, world: &World) {
+        self.dirty.extend(world.chunks().map(|(pos, _)| pos));
+    }
+
+    /// The chunks which could be seen from the camera, see [`visibility::visible_chunks`].
+    pub fn visible_chunks(&self, camera: Vec3, frustum: &Frustum) -> FastHashSet<IVec3> {
+        visibility::visible_chunks(camera, frustum, &self.graphs, |pos| self.in_range(pos))
+    }
+
+    /// The number of chunks still waiting to be generated.
+    pub fn pending(&self) -> usize {
+        self.generating.len()
+    }
+
+    /// The number of edited chunks still waiting to be remeshed.
+    pub fn pending_remeshes(&self) -> usize {
+        self.remesh.len()
+    }
+
+    /// Moves the loaded area to follow the camera, collects finished work and queues new work.
+    pub fn update(&mut self, world: &mut World, camera: Vec3) -> StreamUpdate {
+        let mut update = StreamUpdate::default();
+
+        let (center, _) = World::split_pos(camera.floor().as_ivec3());
+        if self.center != Some(center) {
+            self.recenter(world, center, &mut update);
+        }
+
+        for done in self.pool.finished.try_iter() {
+            match done {
+                Done::Generated(pos, mut chunk, pending) => {
+                    self.generating.remove(&pos);
+                    // The camera may have moved away, or the chunk was created by an edit meanwhile.
+                    if !self.in_range(pos) || world.is_loaded(pos) {
+                        continue;
+                    }
+
+                    chunk.set_mesh_mode(self.mesh_mode);
+                    world.insert_chunk(pos, *chunk);
+                    self.dirty.insert(pos);
+
+                    let registry = &self.pool.context.registry;
+                    let mut relit = world.spread_light(pos, registry);
+
+                    // Structures reaching across the border, from this chunk or into it.
+                    let mut placed = world.apply_pending(pos, registry);
+                    placed.extend(world.place_pending(pending));
+                    relit.extend(world.update_lights(placed, registry));
+                    self.dirty.extend(
+                        relit
+                            .into_iter()
+                            .filter(|pos| self.versions.contains_key(pos)),
+                    );
+
+                    // Meshed neighbors drew faces and occlusion against this chunk while it was missing.
+                    for i in 0..NEIGHBORHOOD {
+                        let neighbor = pos + IVec3::from(neighbor_offset(i));
+                        if self.versions.contains_key(&neighbor) {
+                            self.dirty.insert(neighbor);
+                        }
+                    }
+                }
+                // The chunk stays unloaded rather than being regenerated, which would overwrite
+                // its save when unloaded. It is tried again the next time the camera moves.
+                Done::Failed(pos, e) => {
+                    self.generating.remove(&pos);
+                    log::error!("Loading chunk {pos} failed with error: {e}");
+                }
+                Done::Meshed(pos, builder, version, graph) => {
+                    if self.versions.get(&pos) == Some(&version) && world.is_loaded(pos) {
+                        self.graphs.insert(pos, graph);
+                        update.meshed.push((pos, builder));
+                    }
+                }
+            }
+        }
+
+        // Chunks which were never meshed are left to the workers.
+        for pos in world.take_dirty() {
+            if self.versions.contains_key(&pos) && !self.dirty.contains(&pos) {
+                self.remesh.insert(pos);
+            }
+        }
+        self.remesh_edited(world, &mut update);
+
+        self.queue_meshes(world);
+        update
+    }
+
+    /// Remeshes edited chunks nearest the camera first, until the time budget runs out.
+    fn remesh_edited(&mut self, world: &World, update: &mut StreamUpdate) {
+        if self.remesh.is_empty() {
+            return;
+        }
+
+        let center = self.center.unwrap_or_default();
+        let mut queue = self.remesh.iter().copied().collect::<Vec<_>>();
+        queue.sort_unstable_by_key(|pos| (*pos - center).length_squared());
+
+        let start = Instant::now();
+        for (i, pos) in queue.into_iter().enumerate() {
+            if i > 0 && start.elapsed() >= self.remesh_budget {
+                break;
+            }
+            self.remesh.remove(&pos);
+            if let Some(builder) = self.mesh_now(world, pos) {
+                update.meshed.push((pos, builder));
+            }
+        }
+    }
+
+    fn recenter(&mut self, world: &mut World, center: IVec3, update: &mut StreamUpdate) {
+        self.center = Some(center);
+
+        // Drop queued chunks which left the radius before a worker got to them.
+        let mut queue = self.pool.shared.queue.lock().unwrap();
+        queue.center = center;
+        queue.jobs.retain(|job| match job.task {
+            Task::Generate => {
+                let keep = in_range(center, job.pos, self.radius, self.vertical_radius);
+                if !keep {
+                    self.generating.remove(&job.pos);
+                }
+                keep
+            }
+            Task::Mesh { .. } => true,
+        });
+
+        // Unload a chunk further out than loading, so moving back and forth over a chunk border
+        // doesn't reload the same chunks.
+        let unloaded = world
+            .chunks()
+            .map(|(pos, _)| pos)
+            .filter(|pos| !in_range(center, *pos, self.radius + 1, self.vertical_radius + 1))
+            .collect::<Vec<_>>();
+        if world.save_dir().is_some() && !unloaded.is_empty() {
+            if let Err(e) = world.save_chunks(unloaded.iter().copied(), &self.pool.context.registry)
+            {
+                log::error!("Saving unloaded chunks failed with error: {e}");
+            }
+        }
+        for pos in &unloaded {
+            world.remove_chunk(*pos);
+            self.dirty.remove(pos);
+            self.remesh.remove(pos);
+            self.versions.remove(pos);
+            self.levels.remove(pos);
+            self.graphs.remove(pos);
+        }
+        update.unloaded.extend(unloaded);
+
+        // Chunks whose level of detail changed are remeshed, along with their neighbors, which cull
+        // their border faces against it.
+        let changed = self
+            .levels
+            .iter()
+            .filter(|(pos, level)| self.lod(**pos) != **level)
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        for pos in changed {
+            self.dirty.insert(pos);
+            for offset in FACE_OFFSETS {
+                let neighbor = pos + IVec3::from(offset);
+                if self.versions.contains_key(&neighbor) {
+                    self.dirty.insert(neighbor);
+                }
+            }
+        }
+
+        let (r, vr) = (self.radius, self.vertical_radius);
+        for x in -r..=r {
+            for y in -vr..=vr {
+                for z in -r..=r {
+                    let pos = center + IVec3::new(x, y, z);
+                    if self.in_range(pos) && !world.is_loaded(pos) && self.generating.insert(pos) {
+                        queue.jobs.push(Job {
+                            pos,
+                            task: Task::Generate,
+                        });
+                    }
+                }
+            }
+        }
+
+        drop(queue);
+        self.pool.shared.ready.notify_all();
+    }
+
+    /// Sends dirty chunks to be meshed, once every neighbor they will ever have is loaded.
+    /// Waiting for the neighbors avoids meshing chunks twice while the area around them fills in.
+    fn queue_meshes(&mut self, world: &World) {
+        let ready = self
+            .dirty
+            .iter()
+            .copied()
+            .filter(|pos| {
+                (0..NEIGHBORHOOD).all(|i| {
+                    let neighbor = *pos + IVec3::from(neighbor_offset(i));
+                    world.is_loaded(neighbor) || !self.generating.contains(&neighbor)
+                })
+            })
+            .collect::<Vec<_>>();
+        if ready.is_empty() {
+            return;
+        }
+
+        let mut queue = self.pool.shared.queue.lock().unwrap();
+        for pos in ready {
+            self.dirty.remove(&pos);
+            let Some(chunk) = world.shared_chunk(pos) else {
+                continue;
+            };
+
+            self.next_version += 1;
+            self.versions.insert(pos, self.next_version);
+            let level = self.lod(pos);
+            self.levels.insert(pos, level);
+            queue.jobs.push(Job {
+                pos,
+                task: Task::Mesh {
+                    chunk,
+                    neighbors: Box::new(std::array::from_fn(|i| {
+                        world.shared_chunk(pos + IVec3::from(neighbor_offset(i)))
+                    })),
+                    level,
+                    lods: self.neighbor_lods(pos),
+                    version: self.next_version,
+                },
+            });
+        }
+
+        drop(queue);
+        self.pool.shared.ready.notify_all();
+    }
+
+    /// The level of detail a chunk should be drawn at, from its distance to the camera.
+    fn lod(&self, pos: IVec3) -> u8 {
+        match (self.lod_distance, self.center) {
+            (Some(lod_distance), Some(center)) => {
+                lod::lod_level((pos - center).as_vec3().length(), lod_distance)
+            }
+            _ => 0,
+        }
+    }
+
+    fn neighbor_lods(&self, pos: IVec3) -> [u8; 6] {
+        FACE_OFFSETS.map(|offset| self.lod(pos + IVec3::from(offset)))
+    }
+
+    fn in_range(&self, pos: IVec3) -> bool {
+        self.center
+            .is_some_and(|center| in_range(center, pos, self.radius, self.vertical_radius))
+    }
+}
+
+/// Whether a chunk is inside the cylinder of chunks loaded around `center`.
+fn in_range(center: IVec3, pos: IVec3, radius: i32, vertical_radius: i32) -> bool {
+    let offset = pos - center;
+    offset.x * offset.x + offset.z * offset.z <= radius * radius
+        && offset.y.abs() <= vertical_radius
+}
+
+enum Task {
+    Generate,
+    Mesh {
+        chunk: Arc<Chunk>,
+        neighbors: Box<[Option<Arc<Chunk>>; NEIGHBORHOOD]>,
+        level: u8,
+        lods: [u8; 6],
+        version: u64,
+    },
+}
+
+struct Job {
+    pos: IVec3,
+    task: Task,
+}
+
+enum Done {
+    /// A chunk, along with the tiles it generated outside of itself.
+    Generated(IVec3, Box<Chunk>, Vec<PendingTile>),
+    /// A chunk whose save couldn't be read.
+    Failed(IVec3, SaveError),
+    /// A mesh, along with the visibility graph of the chunk it was built from.
+    Meshed(IVec3, MeshBuilder, u64, VisibilityGraph),
+}
+
+struct Queue {
+    jobs: Vec<Job>,
+    /// Jobs nearest to this chunk are picked first.
+    center: IVec3,
+    shutdown: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+/// Everything a worker needs to load, generate and mesh chunks.
+struct WorkerContext {
+    registry: Arc<TileRegistry>,
+    generator: Option<Arc<dyn WorldGenerator>>,
+    save_dir: Option<PathBuf>,
+}
+
+struct WorkerPool {
+    shared: Arc<Shared>,
+    context: Arc<WorkerContext>,
+    finished: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    fn new(threads: usize, context: WorkerContext) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: vec![],
+                center: IVec3::ZERO,
+                shutdown: false,
+            }),
+            ready: Condvar::new(),
+        });
+        let context = Arc::new(context);
+        let (sender, finished) = mpsc::channel();
+
+        let workers = (0..threads)
+            .map(|i| {
+                let shared = shared.clone();
+                let context = context.clone();
+                let sender = sender.clone();
+                thread::Builder::new()
+                    .name(format!("chunk worker {i}"))
+                    .spawn(move || work(&shared, &context, &sender))
+                    .expect("Chunk worker thread should spawn")
+            })
+            .collect();
+
+        Self {
+            shared,
+            context,
+            finished,
+            workers,
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.ready.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(shared: &Shared, context: &WorkerContext, sender: &Sender<Done>) {
+    loop {
+        let job = {
+            let mut queue = shared.queue.lock().unwrap();
+            while queue.jobs.is_empty() && !queue.shutdown {
+                queue = shared.ready.wait(queue).unwrap();
+            }
+            if queue.shutdown {
+                return;
+            }
+
+            let center = queue.center;
+            let nearest = queue
+                .jobs
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, job)| (job.pos - center).length_squared())
+                .map(|(i, _)| i)
+                .unwrap();
+            queue.jobs.swap_remove(nearest)
+        };
+
+        let done = match job.task {
+            Task::Generate => match context.load_or_generate(job.pos) {
+                Ok((chunk, pending)) => Done::Generated(job.pos, Box::new(chunk), pending),
+                Err(e) => Done::Failed(job.pos, e),
+            },
+            Task::Mesh {
+                chunk,
+                neighbors,
+                level,
+                lods,
+                version,
+            } => {
+                let neighbors =
+                    ChunkNeighbors::from_fn(|offset| neighbors[neighbor_index(offset)].as_deref())
+                        .with_lods(lods);
+                let builder = chunk.build_lod_mesh(
+                    &context.registry,
+                    &neighbors,
+                    level,
+                    World::chunk_origin(job.pos).as_vec3().to_array(),
+                );
+                let graph = VisibilityGraph::new(&chunk, &context.registry);
+                Done::Meshed(job.pos, builder, version, graph)
+            }
+        };
+
+        // The streamer was dropped, so nobody wants the result.
+        if sender.send(done).is_err() {
+            return;
+        }
+    }
+}
+
+impl WorkerContext {
+    /// Loads or generates a chunk, lighting it on its own. Chunks are only generated if they aren't
+    /// saved, so a save which can't be read is an error instead.
+    /// Returns the tiles it generated outside of itself, which a loaded chunk has none of.
+    fn load_or_generate(&self, pos: IVec3) -> Result<(Chunk, Vec<PendingTile>), SaveError> {
+        let (mut chunk, pending) = match self.load(pos)? {
+            Some(chunk) => (chunk, vec![]),
+            None => {
+                let mut chunk = Chunk::new();
+                let pending = match &self.generator {
+                    Some(generator) => generator.generate(pos, &mut chunk),
+                    None => vec![],
+                };
+                (chunk, pending)
+            }
+        };
+
+        light::light_chunk(&mut chunk, pos, &self.registry);
+        Ok((chunk, pending))
+    }
+
+    fn load(&self, pos: IVec3) -> Result<Option<Chunk>, SaveError> {
+        match &self.save_dir {
+            Some(dir) => region::load_chunk(dir, pos, &self.registry),
+            None => Ok(None),
+        }
+    }
+}
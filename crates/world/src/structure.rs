@@ -0,0 +1,195 @@
+use std::{fs, path::Path};
+
+use glam::IVec3;
+use serde::Deserialize;
+use thiserror::Error;
+use wgpu::naga::FastHashMap;
+
+use render::mesh::builder::MeshBuilder;
+
+use crate::{
+    edit::{ChangeSet, Voxel},
+    light,
+    tile::{TileId, TileRegistry, TileState},
+    world::World,
+};
+
+#[derive(Error, Debug)]
+pub enum StructureError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
+    #[error("Structure uses tile `{0}`, which is not registered")]
+    UnknownTile(String),
+
+    #[error("Structure uses `{0}`, which is not in its palette")]
+    UnknownSymbol(char),
+
+    #[error("Palette keys should be a single character, but `{0}` isn't")]
+    InvalidSymbol(String),
+
+    #[error("Every layer of a structure should have the same number of rows of the same length")]
+    UnevenLayers,
+}
+
+/// A structure as written in a toml file.
+#[derive(Deserialize)]
+struct StructureFile {
+    #[serde(default)]
+    anchor: [i32; 3],
+    palette: FastHashMap<String, String>,
+    layers: Vec<Vec<String>>,
+}
+
+/// A template of tiles, like a tree or a ruin, placed into the world by its anchor.
+///
+/// Locations without a tile leave whatever was there before, so a structure only overwrites the
+/// tiles it actually has.
+#[derive(Clone, Debug)]
+pub struct Structure {
+    size: IVec3,
+    /// The location in the template which lands on the position the structure is placed at.
+    pub anchor: IVec3,
+    tiles: Vec<Option<TileId>>,
+}
+
+impl Structure {
+    /// Creates an empty structure.
+    pub fn new(size: IVec3, anchor: IVec3) -> Self {
+        let size = size.max(IVec3::ZERO);
+        Self {
+            size,
+            anchor,
+            tiles: vec![None; (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    /// Reads a structure from toml, made of layers of characters from the bottom up.
+    /// Each row of a layer runs along x, and the rows of a layer run along z. Characters map to
+    /// tiles through the palette, while `.` and spaces leave the world alone.
+    pub fn from_toml(src: &str, registry: &TileRegistry) -> Result<Self, StructureError> {
+        let file: StructureFile = toml::from_str(src)?;
+
+        let mut palette = FastHashMap::default();
+        for (symbol, name) in &file.palette {
+            let mut chars = symbol.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                return Err(StructureError::InvalidSymbol(symbol.clone()));
+            };
+            let tile = registry
+                .id(name)
+                .ok_or_else(|| StructureError::UnknownTile(name.clone()))?;
+            palette.insert(c, tile);
+        }
+
+        let depth = file.layers.first().map_or(0, |layer| layer.len());
+        let width = file
+            .layers
+            .first()
+            .and_then(|layer| layer.first())
+            .map_or(0, |row| row.chars().count());
+        let size = IVec3::new(width as i32, file.layers.len() as i32, depth as i32);
+
+        let mut structure = Self::new(size, file.anchor.into());
+        for (y, layer) in file.layers.iter().enumerate() {
+            if layer.len() != depth {
+                return Err(StructureError::UnevenLayers);
+            }
+            for (z, row) in layer.iter().enumerate() {
+                if row.chars().count() != width {
+                    return Err(StructureError::UnevenLayers);
+                }
+                for (x, c) in row.chars().enumerate() {
+                    let tile = match c {
+                        '.' | ' ' => continue,
+                        c => *palette.get(&c).ok_or(StructureError::UnknownSymbol(c))?,
+                    };
+                    structure.set(IVec3::new(x as i32, y as i32, z as i32), Some(tile));
+                }
+            }
+        }
+        Ok(structure)
+    }
+
+    pub fn load(path: impl AsRef<Path>, registry: &TileRegistry) -> Result<Self, StructureError> {
+        Self::from_toml(&fs::read_to_string(path)?, registry)
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    /// The tile at a location in the template, or `None` outside of it.
+    pub fn get(&self, loc: IVec3) -> Option<TileId> {
+        self.index(loc).and_then(|i| self.tiles[i])
+    }
+
+    /// Sets the tile at a location in the template. Locations outside of it are ignored.
+    pub fn set(&mut self, loc: IVec3, tile: Option<TileId>) {
+        if let Some(i) = self.index(loc) {
+            self.tiles[i] = tile;
+        }
+    }
+
+    /// Every tile of the structure, at the world position it lands on when placed at `pos`.
+    pub fn tiles_at(&self, pos: IVec3) -> impl Iterator<Item = (IVec3, TileId)> + '_ {
+        let offset = pos - self.anchor;
+        let size = self.size;
+        self.tiles.iter().enumerate().filter_map(move |(i, tile)| {
+            let i = i as i32;
+            let loc = IVec3::new(i % size.x, i / size.x % size.y, i / (size.x * size.y));
+            Some((offset + loc, (*tile)?))
+        })
+    }
+
+    /// Meshes the structure with the chunk mesher, with its anchor at the origin, lit as if it stood
+    /// alone under open sky.
+    pub fn build_mesh(&self, registry: &TileRegistry) -> MeshBuilder {
+        let mut world = World::new(0);
+        for (pos, tile) in self.tiles_at(IVec3::ZERO) {
+            world.set_tile(pos, Some(tile));
+        }
+
+        let chunks = world.chunks().map(|(pos, _)| pos).collect::<Vec<_>>();
+        for pos in &chunks {
+            light::light_chunk(world.chunk_mut(*pos).unwrap(), *pos, registry);
+        }
+        for pos in &chunks {
+            world.spread_light(*pos, registry);
+        }
+
+        let mut builder = MeshBuilder::default();
+        for pos in chunks {
+            let offset = World::chunk_origin(pos).as_vec3().to_array();
+            let chunk = world.chunk(pos).unwrap();
+            builder.append(chunk.build_mesh(registry, &world.neighbors(pos), offset));
+        }
+        builder
+    }
+
+    fn index(&self, loc: IVec3) -> Option<usize> {
+        if loc.cmplt(IVec3::ZERO).any() || loc.cmpge(self.size).any() {
+            return None;
+        }
+        Some((loc.x + loc.y * self.size.x + loc.z * self.size.x * self.size.y) as usize)
+    }
+}
+
+impl World {
+    /// Stamps a structure into the world with its anchor at `pos`, returning what changed so it can
+    /// be undone.
+    pub fn place_structure(
+        &mut self,
+        structure: &Structure,
+        pos: IVec3,
+        registry: &TileRegistry,
+    ) -> ChangeSet {
+        let voxels = structure
+            .tiles_at(pos)
+            .map(|(pos, tile)| (pos, Voxel::new(Some(tile), TileState::default())));
+        self.apply_edit(voxels, registry)
+    }
+}
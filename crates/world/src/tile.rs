@@ -1 +1,506 @@
-pub struct Tile {}
+use std::{fs, ops::Neg, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use wgpu::naga::FastHashMap;
+
+use crate::{
+    chunk::FACE_OFFSETS,
+    light::MAX_LIGHT,
+    shape::{TileModel, TileShape},
+};
+
+#[derive(Error, Debug)]
+pub enum TileRegistryError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
+    #[error("Tile `{0}` was registered more than once")]
+    DuplicateTile(String),
+
+    #[error("Tile registry is full, only {} tiles are supported", u16::MAX)]
+    TooManyTiles,
+
+    #[error("Tile `{0}` has too many states, only {} are supported", u16::MAX as usize + 1)]
+    TooManyStates(String),
+
+    #[error("Property `{property}` of tile `{tile}` has no values")]
+    EmptyProperty { tile: String, property: String },
+
+    #[error("Tile `{0}` emits light {1}, but light only goes up to {MAX_LIGHT}")]
+    LightTooBright(String, u8),
+
+    #[error("Fluid `{0}` must drop at least one level with each tile it flows")]
+    NoFluidDrop(String),
+
+    #[error("Tile `{0}` has a box which doesn't fit inside the tile")]
+    BoxOutOfBounds(String),
+
+    #[error("Tile `{tile}` has no property `{property}` with the value `{value}`")]
+    UnknownPropertyValue {
+        tile: String,
+        property: String,
+        value: String,
+    },
+}
+
+/// A handle to a tile type registered in a [`TileRegistry`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TileId(pub u16);
+
+/// The state of a tile, like which way it faces. It indexes every combination of the values of the
+/// tile's [`TileDef::properties`], so state 0 takes the first value of each.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TileState(pub u16);
+
+/// A property of a tile's state, like the axis a log lies along.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TileProperty {
+    pub name: String,
+    /// Every value the property can take, starting with the default.
+    pub values: Vec<String>,
+}
+
+/// Changes how a tile is drawn in the states that match it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileVariant {
+    /// The value of each property the variant applies to. Properties left out match every value.
+    pub when: FastHashMap<String, String>,
+    /// Replaces the tile's textures.
+    pub textures: Option<TileTextures>,
+    /// Quarter turns around the x axis, applied before `rotate_y`.
+    pub rotate_x: u8,
+    /// Quarter turns around the y axis.
+    pub rotate_y: u8,
+}
+
+impl TileVariant {
+    /// The face of the unrotated tile which ends up facing `face`.
+    pub fn source_face(&self, face: usize) -> usize {
+        (0..FACE_OFFSETS.len())
+            .find(|source| self.rotate_face(*source) == face)
+            .unwrap()
+    }
+
+    /// The face a face of the unrotated tile ends up facing.
+    pub fn rotate_face(&self, face: usize) -> usize {
+        let dir = self.turn(FACE_OFFSETS[face]);
+        FACE_OFFSETS.iter().position(|f| *f == dir).unwrap()
+    }
+
+    /// Rotates a point of the unrotated tile around the tile's center, with the tile running from 0
+    /// to 1 along each axis.
+    pub fn rotate_point(&self, point: [f32; 3]) -> [f32; 3] {
+        self.turn(point.map(|v| v - 0.5)).map(|v| v + 0.5)
+    }
+
+    fn turn<T: Copy + Neg<Output = T>>(&self, [mut x, mut y, mut z]: [T; 3]) -> [T; 3] {
+        for _ in 0..self.rotate_x % 4 {
+            (y, z) = (-z, y);
+        }
+        for _ in 0..self.rotate_y % 4 {
+            (x, z) = (-z, x);
+        }
+        [x, y, z]
+    }
+}
+
+/// Texture names for each face of a tile.
+/// More specific entries take priority, so `top` wins over `all`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileTextures {
+    pub all: Option<String>,
+    pub side: Option<String>,
+
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub front: Option<String>,
+    pub back: Option<String>,
+}
+
+impl TileTextures {
+    /// Returns the texture for a face, using the same face order as `MeshBuilder`.
+    pub fn face(&self, face: usize) -> Option<&str> {
+        let specific = match face {
+            0 => &self.top,
+            1 => &self.bottom,
+            2 => &self.left,
+            3 => &self.right,
+            4 => &self.front,
+            5 => &self.back,
+            _ => &None,
+        };
+
+        let side = match face {
+            2..=5 => &self.side,
+            _ => &None,
+        };
+
+        specific
+            .as_ref()
+            .or(side.as_ref())
+            .or(self.all.as_ref())
+            .map(|t| t.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TileDef {
+    pub name: String,
+
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub transparent: bool,
+
+    #[serde(default)]
+    pub textures: TileTextures,
+    /// The shape the tile is drawn and collides with, rotated by its variants.
+    #[serde(default)]
+    pub shape: TileShape,
+
+    /// Light emitted by the tile, from 0 (none) to 15.
+    #[serde(default)]
+    pub light: u8,
+
+    /// Makes the tile a fluid, which flows as described by [`crate::fluid`].
+    #[serde(default)]
+    pub fluid: Option<FluidDef>,
+
+    /// The properties making up the tile's [`TileState`].
+    #[serde(default)]
+    pub properties: Vec<TileProperty>,
+    /// Ways of drawing the tile in different states. The first one matching a state is used.
+    #[serde(default)]
+    pub variants: Vec<TileVariant>,
+}
+
+fn default_solid() -> bool {
+    true
+}
+
+/// How a fluid tile flows.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FluidDef {
+    /// How many levels the fluid loses with each tile it flows sideways, out of
+    /// [`crate::fluid::FLUID_SOURCE`].
+    #[serde(default = "default_fluid_drop")]
+    pub drop: u8,
+    /// How many simulation ticks pass between each step of the flow.
+    #[serde(default = "default_fluid_interval")]
+    pub tick_interval: u32,
+}
+
+fn default_fluid_drop() -> u8 {
+    1
+}
+
+fn default_fluid_interval() -> u32 {
+    5
+}
+
+impl TileDef {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            solid: true,
+            transparent: false,
+            textures: TileTextures::default(),
+            shape: TileShape::Cube,
+            light: 0,
+            fluid: None,
+            properties: vec![],
+            variants: vec![],
+        }
+    }
+
+    /// The number of different states the tile can be in.
+    pub fn state_count(&self) -> usize {
+        self.properties.iter().map(|p| p.values.len()).product()
+    }
+
+    /// The value of a property in a state, or `None` if the tile doesn't have the property.
+    pub fn property(&self, state: TileState, name: &str) -> Option<&str> {
+        // States count through the values of the first property fastest.
+        let mut state = state.0 as usize;
+        for property in &self.properties {
+            let value = state % property.values.len();
+            if property.name == name {
+                return Some(&property.values[value]);
+            }
+            state /= property.values.len();
+        }
+        None
+    }
+
+    /// Changes the value of a property in a state, or returns `None` if the tile doesn't have the
+    /// property or value.
+    pub fn with_property(&self, state: TileState, name: &str, value: &str) -> Option<TileState> {
+        let mut stride = 1;
+        for property in &self.properties {
+            let len = property.values.len();
+            if property.name == name {
+                let value = property.values.iter().position(|v| v == value)?;
+                let current = state.0 as usize / stride % len;
+                let state = state.0 as usize - current * stride + value * stride;
+                return Some(TileState(state as u16));
+            }
+            stride *= len;
+        }
+        None
+    }
+
+    /// The variant used to draw a state, if any matches it.
+    pub fn variant(&self, state: TileState) -> Option<&TileVariant> {
+        self.variants.iter().find(|variant| {
+            variant
+                .when
+                .iter()
+                .all(|(name, value)| self.property(state, name) == Some(value))
+        })
+    }
+
+    /// Checks that every property has values, that the states fit in a [`TileState`], that light
+    /// fits in a light level, that fluids drain as they flow, and that the variants only refer to
+    /// values which exist.
+    fn validate(&self) -> Result<(), TileRegistryError> {
+        if let Some(property) = self.properties.iter().find(|p| p.values.is_empty()) {
+            return Err(TileRegistryError::EmptyProperty {
+                tile: self.name.clone(),
+                property: property.name.clone(),
+            });
+        }
+        if self.state_count() > u16::MAX as usize + 1 {
+            return Err(TileRegistryError::TooManyStates(self.name.clone()));
+        }
+        if !self.shape.fits() {
+            return Err(TileRegistryError::BoxOutOfBounds(self.name.clone()));
+        }
+        if self.light > MAX_LIGHT {
+            return Err(TileRegistryError::LightTooBright(
+                self.name.clone(),
+                self.light,
+            ));
+        }
+        // Without a drop, flowing fluid would spread at full level forever and never settle.
+        if self.fluid.as_ref().is_some_and(|f| f.drop == 0) {
+            return Err(TileRegistryError::NoFluidDrop(self.name.clone()));
+        }
+
+        for (property, value) in self.variants.iter().flat_map(|v| &v.when) {
+            let exists = self
+                .properties
+                .iter()
+                .any(|p| p.name == *property && p.values.contains(value));
+            if !exists {
+                return Err(TileRegistryError::UnknownPropertyValue {
+                    tile: self.name.clone(),
+                    property: property.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the tile hides the faces of the tiles next to it, wherever its shape covers them.
+    /// Fluids never do, since they are usually lower than a full tile.
+    pub fn opaque(&self) -> bool {
+        !self.transparent && self.fluid.is_none()
+    }
+
+    /// Whether the tile fully hides the tiles next to it, blocking light and casting ambient
+    /// occlusion, which only opaque cubes do.
+    pub fn occludes(&self) -> bool {
+        self.opaque() && self.shape == TileShape::Cube
+    }
+}
+
+#[derive(Deserialize)]
+struct TileFile {
+    #[serde(default)]
+    tile: Vec<TileDef>,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct TileRegistry {
+    tiles: Vec<TileDef>,
+    names: FastHashMap<String, TileId>,
+    /// The texture array layer of each face of each state of each tile.
+    layers: Vec<Vec<[u32; 6]>>,
+    /// The model of each state of each tile.
+    models: Vec<Vec<TileModel>>,
+}
+
+impl TileRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_toml(src: &str) -> Result<Self, TileRegistryError> {
+        let mut registry = Self::new();
+        registry.load_toml(src)?;
+        Ok(registry)
+    }
+
+    /// Loads a single toml file, or every toml file in a directory in name order.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TileRegistryError> {
+        let path = path.as_ref();
+        let mut registry = Self::new();
+        if path.is_dir() {
+            registry.load_dir(path)?;
+        } else {
+            registry.load_file(path)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn load_toml(&mut self, src: &str) -> Result<(), TileRegistryError> {
+        let file: TileFile = toml::from_str(src)?;
+        for def in file.tile {
+            self.register(def)?;
+        }
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), TileRegistryError> {
+        self.load_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn load_dir(&mut self, path: impl AsRef<Path>) -> Result<(), TileRegistryError> {
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                files.push(path);
+            }
+        }
+
+        // Sort so that tile ids stay the same between runs.
+        files.sort();
+        for file in files {
+            self.load_file(file)?;
+        }
+        Ok(())
+    }
+
+    pub fn register(&mut self, def: TileDef) -> Result<TileId, TileRegistryError> {
+        if self.names.contains_key(&def.name) {
+            return Err(TileRegistryError::DuplicateTile(def.name));
+        }
+        if self.tiles.len() >= u16::MAX as usize {
+            return Err(TileRegistryError::TooManyTiles);
+        }
+        def.validate()?;
+
+        let id = TileId(self.tiles.len() as u16);
+        self.names.insert(def.name.clone(), id);
+        self.layers.push(vec![[0; 6]; def.state_count()]);
+        self.models.push(
+            (0..def.state_count())
+                .map(|state| TileModel::new(&def, TileState(state as u16)))
+                .collect(),
+        );
+        self.tiles.push(def);
+        Ok(id)
+    }
+
+    /// Resolves the texture names of every state of every tile into texture array layers, rotated
+    /// and replaced by the tile's variants. Faces with a missing texture fall back to layer 0.
+    pub fn bind_textures(&mut self, layers: &FastHashMap<String, u32>) {
+        for (def, states) in self.tiles.iter().zip(&mut self.layers) {
+            for (state, faces) in states.iter_mut().enumerate() {
+                let variant = def.variant(TileState(state as u16));
+                let textures = variant
+                    .and_then(|v| v.textures.as_ref())
+                    .unwrap_or(&def.textures);
+
+                *faces = std::array::from_fn(|face| {
+                    let face = variant.map_or(face, |v| v.source_face(face));
+                    let Some(name) = textures.face(face) else {
+                        return 0;
+                    };
+                    layers.get(name).copied().unwrap_or_else(|| {
+                        log::warn!(
+                            "Tile `{}` uses texture `{name}`, which was not found",
+                            def.name
+                        );
+                        0
+                    })
+                });
+            }
+        }
+    }
+
+    /// The texture array layer of a face of a tile in a state, set by [`Self::bind_textures`].
+    /// States the tile doesn't have are drawn as the default state.
+    pub fn texture_layer(&self, id: TileId, state: TileState, face: usize) -> u32 {
+        let states = &self.layers[id.0 as usize];
+        states.get(state.0 as usize).unwrap_or(&states[0])[face]
+    }
+
+    /// The model of a tile in a state. States the tile doesn't have use the default state's model.
+    pub fn model(&self, id: TileId, state: TileState) -> &TileModel {
+        let states = &self.models[id.0 as usize];
+        states.get(state.0 as usize).unwrap_or(&states[0])
+    }
+
+    /// Returns the definition of a tile.
+    /// Panics if the id was not created by this registry.
+    pub fn get(&self, id: TileId) -> &TileDef {
+        &self.tiles[id.0 as usize]
+    }
+
+    pub fn id(&self, name: &str) -> Option<TileId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TileId, &TileDef)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(i, def)| (TileId(i as u16), def))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fluids_must_drop() {
+        let water = |drop: u8| format!("[[tile]]\nname = \"water\"\nfluid = {{ drop = {drop} }}");
+        assert!(TileRegistry::from_toml(&water(1)).is_ok());
+        assert!(matches!(
+            TileRegistry::from_toml(&water(0)),
+            Err(TileRegistryError::NoFluidDrop(name)) if name == "water"
+        ));
+    }
+
+    #[test]
+    fn light_fits_in_a_level() {
+        let mut lamp = TileDef::new("lamp");
+        lamp.light = MAX_LIGHT;
+        assert!(TileRegistry::new().register(lamp.clone()).is_ok());
+        lamp.light = MAX_LIGHT + 1;
+        assert!(matches!(
+            TileRegistry::new().register(lamp),
+            Err(TileRegistryError::LightTooBright(_, 16))
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+
+use glam::{IVec3, Vec3};
+use wgpu::naga::{FastHashMap, FastHashSet};
+
+use render::frustum::{BoundingBox, Frustum};
+
+use crate::{
+    chunk::{Chunk, CHUNK_SIZE, CHUNK_VOLUME, FACE_OFFSETS},
+    shape::opposite,
+    tile::TileRegistry,
+    world::World,
+};
+
+/// Which faces of a chunk can see each other through the tiles that don't occlude, in the same
+/// order as [`FACE_OFFSETS`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct VisibilityGraph {
+    /// For each face, a bit for every face reachable from it.
+    connections: [u8; 6],
+}
+
+impl VisibilityGraph {
+    /// Every face sees every other, like an empty chunk.
+    pub const ALL: Self = Self {
+        connections: [0b111111; 6],
+    };
+    /// No face sees another, like a solid chunk.
+    pub const NONE: Self = Self {
+        connections: [0; 6],
+    };
+
+    /// Flood fills each open region of a chunk, connecting every pair of faces it touches.
+    pub fn new(chunk: &Chunk, registry: &TileRegistry) -> Self {
+        let index = |[x, y, z]: [usize; 3]| x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE;
+
+        // Cells which are closed or already filled.
+        let mut closed = vec![false; CHUNK_VOLUME];
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let loc = [x, y, z];
+                    closed[index(loc)] = chunk
+                        .get(loc)
+                        .is_some_and(|tile| registry.get(tile).occludes());
+                }
+            }
+        }
+
+        let mut graph = Self::NONE;
+        let mut stack = vec![];
+        for start in 0..CHUNK_VOLUME {
+            if closed[start] {
+                continue;
+            }
+            closed[start] = true;
+            stack.push([
+                start % CHUNK_SIZE,
+                start / CHUNK_SIZE % CHUNK_SIZE,
+                start / (CHUNK_SIZE * CHUNK_SIZE),
+            ]);
+
+            let mut faces = 0u8;
+            while let Some(loc) = stack.pop() {
+                for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+                    let next = [0, 1, 2].map(|i| loc[i] as i32 + offset[i]);
+                    if next.iter().any(|v| *v < 0 || *v >= CHUNK_SIZE as i32) {
+                        faces |= 1 << face;
+                        continue;
+                    }
+                    let next = next.map(|v| v as usize);
+                    if !closed[index(next)] {
+                        closed[index(next)] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+
+            for face in 0..6 {
+                if faces & (1 << face) != 0 {
+                    graph.connections[face] |= faces;
+                }
+            }
+        }
+        graph
+    }
+
+    /// Whether anything entering the chunk through one face can leave through another.
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.connections[from] & (1 << to) != 0
+    }
+}
+
+/// Finds the chunks which could be seen from the camera, by walking outwards from its chunk through
+/// the faces each chunk's [`VisibilityGraph`] connects.
+///
+/// The walk never turns back along a direction it already went, so it can't curl around behind
+/// terrain, and it skips chunks outside the frustum. Chunks without a graph are treated as open.
+/// Only chunks `in_range` are visited.
+pub fn visible_chunks(
+    camera: Vec3,
+    frustum: &Frustum,
+    graphs: &FastHashMap<IVec3, VisibilityGraph>,
+    in_range: impl Fn(IVec3) -> bool,
+) -> FastHashSet<IVec3> {
+    let (start, _) = World::split_pos(camera.floor().as_ivec3());
+    let mut visible = FastHashSet::default();
+    visible.insert(start);
+
+    // Each chunk along with the face it was entered through, and every direction taken to reach it.
+    let mut queue = VecDeque::from([(start, None, 0u8)]);
+    while let Some((pos, entered, directions)) = queue.pop_front() {
+        let graph = graphs.get(&pos).copied().unwrap_or(VisibilityGraph::ALL);
+        for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+            if directions & (1 << opposite(face)) != 0 {
+                continue;
+            }
+            if entered.is_some_and(|entered| !graph.connects(entered, face)) {
+                continue;
+            }
+
+            let next = pos + IVec3::from(*offset);
+            if visible.contains(&next) || !in_range(next) {
+                continue;
+            }
+            let min = World::chunk_origin(next).as_vec3();
+            if !frustum.intersects(&BoundingBox::new(min, min + CHUNK_SIZE as f32)) {
+                continue;
+            }
+
+            visible.insert(next);
+            queue.push_back((next, Some(opposite(face)), directions | (1 << face)));
+        }
+    }
+    visible
+}
@@ -0,0 +1,431 @@
+use std::{fs, path::Path};
+
+use glam::IVec3;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    structure::Structure,
+    tile::{TileId, TileRegistry},
+    world::World,
+};
+
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: u32 = 150;
+/// The largest model MagicaVoxel can hold along each axis.
+pub const MAX_VOX_SIZE: i32 = 256;
+
+#[derive(Error, Debug)]
+pub enum VoxError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
+    #[error("File is not a MagicaVoxel model")]
+    InvalidMagic,
+
+    #[error("Model data is corrupt")]
+    Corrupt,
+
+    #[error("Model has no `{0}` chunk")]
+    MissingChunk(&'static str),
+
+    #[error("Color table uses tile `{0}`, which is not registered")]
+    UnknownTile(String),
+
+    #[error("Tile `{0}` has no color in the color table")]
+    UnmappedTile(String),
+
+    #[error("Region is {0}, but models can be at most {MAX_VOX_SIZE} along each axis")]
+    RegionTooLarge(IVec3),
+
+    #[error("Region uses more than 255 colors")]
+    TooManyColors,
+}
+
+/// Colors as written in a toml file.
+#[derive(Deserialize)]
+struct ColorTableFile {
+    color: Vec<ColorEntry>,
+}
+
+#[derive(Deserialize)]
+struct ColorEntry {
+    color: [u8; 3],
+    tile: String,
+}
+
+/// Maps the colors of MagicaVoxel models to tiles, and tiles back to colors.
+#[derive(Clone, Default, Debug)]
+pub struct ColorTable {
+    colors: Vec<([u8; 3], TileId)>,
+}
+
+impl ColorTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a table of `[[color]]` entries, each with an rgb `color` and the `tile` it maps to.
+    pub fn from_toml(src: &str, registry: &TileRegistry) -> Result<Self, VoxError> {
+        let file: ColorTableFile = toml::from_str(src)?;
+        let mut table = Self::new();
+        for entry in file.color {
+            let tile = registry
+                .id(&entry.tile)
+                .ok_or(VoxError::UnknownTile(entry.tile))?;
+            table = table.with_color(entry.color, tile);
+        }
+        Ok(table)
+    }
+
+    pub fn with_color(mut self, color: [u8; 3], tile: TileId) -> Self {
+        self.colors.push((color, tile));
+        self
+    }
+
+    /// The tile of the closest color in the table, so slightly different shades still map.
+    pub fn tile(&self, color: [u8; 3]) -> Option<TileId> {
+        let distance = |other: [u8; 3]| {
+            (0..3)
+                .map(|i| (color[i] as i32 - other[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        self.colors
+            .iter()
+            .min_by_key(|(other, _)| distance(*other))
+            .map(|(_, tile)| *tile)
+    }
+
+    /// The first color in the table mapping to a tile.
+    pub fn color(&self, tile: TileId) -> Option<[u8; 3]> {
+        self.colors
+            .iter()
+            .find(|(_, other)| *other == tile)
+            .map(|(color, _)| *color)
+    }
+}
+
+/// The palette of models saved without an `RGBA` chunk, laid out like one: the color of each color
+/// index from 1 up, as rgba. It steps through a cube of colors from white to blue, followed by ramps of
+/// red, green, blue and gray.
+fn default_palette() -> Vec<u8> {
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let shades = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut colors = vec![];
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                colors.push([r, g, b]);
+            }
+        }
+    }
+    // Black is left out of the cube.
+    colors.pop();
+    for channel in 0..3 {
+        colors.extend(shades.map(|shade| {
+            let mut color = [0; 3];
+            color[channel] = shade;
+            color
+        }));
+    }
+    colors.extend(shades.map(|shade| [shade; 3]));
+
+    let mut palette = vec![0; 256 * 4];
+    for (i, [r, g, b]) in colors.into_iter().enumerate() {
+        palette[i * 4..i * 4 + 4].copy_from_slice(&[r, g, b, 255]);
+    }
+    palette
+}
+
+/// The position of a voxel in a model of `size`, as a location in a template. MagicaVoxel points z
+/// up, so its y axis runs backwards along z to keep models from being mirrored.
+fn from_vox(voxel: IVec3, size: IVec3) -> IVec3 {
+    IVec3::new(voxel.x, voxel.z, size.y - 1 - voxel.y)
+}
+
+impl Structure {
+    /// Reads the first model of a MagicaVoxel file, anchored at the middle of its bottom.
+    /// Each voxel becomes the tile its color maps to in `colors`. Files without a palette use
+    /// MagicaVoxel's default one.
+    pub fn from_vox(bytes: &[u8], colors: &ColorTable) -> Result<Self, VoxError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(VoxError::InvalidMagic);
+        }
+        let _version = reader.u32()?;
+
+        // The main chunk holds everything else as its children.
+        let (id, _, mut children) = reader.chunk()?;
+        if id != b"MAIN" {
+            return Err(VoxError::MissingChunk("MAIN"));
+        }
+
+        let (mut size, mut voxels, mut palette) = (None, None, None);
+        while !children.bytes.is_empty() {
+            let (id, mut content, _) = children.chunk()?;
+            match id {
+                b"SIZE" if size.is_none() => {
+                    let [x, y, z] = [(); 3].map(|_| content.u32());
+                    size = Some(IVec3::new(x? as i32, y? as i32, z? as i32));
+                }
+                b"XYZI" if voxels.is_none() => {
+                    let count = content.u32()? as usize;
+                    voxels = Some(content.take(count.checked_mul(4).ok_or(VoxError::Corrupt)?)?);
+                }
+                b"RGBA" => palette = Some(content.take(256 * 4)?),
+                // Scenes, layers and materials don't matter to the tiles.
+                _ => {}
+            }
+        }
+        let size = size.ok_or(VoxError::MissingChunk("SIZE"))?;
+        let voxels = voxels.ok_or(VoxError::MissingChunk("XYZI"))?;
+        let palette = palette.map_or_else(default_palette, <[u8]>::to_vec);
+        if size.cmplt(IVec3::ZERO).any() || size.cmpgt(IVec3::splat(MAX_VOX_SIZE)).any() {
+            return Err(VoxError::Corrupt);
+        }
+
+        let template_size = IVec3::new(size.x, size.z, size.y);
+        let anchor = IVec3::new(template_size.x / 2, 0, template_size.z / 2);
+        let mut structure = Structure::new(template_size, anchor);
+        for voxel in voxels.chunks_exact(4) {
+            // Color indices start at 1, and index the palette from its first color.
+            let Some(i) = (voxel[3] as usize).checked_sub(1) else {
+                continue;
+            };
+            let color = [0, 1, 2].map(|c| palette[i * 4 + c]);
+            let pos = IVec3::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32);
+            structure.set(from_vox(pos, size), colors.tile(color));
+        }
+        Ok(structure)
+    }
+
+    pub fn load_vox(path: impl AsRef<Path>, colors: &ColorTable) -> Result<Self, VoxError> {
+        Self::from_vox(&fs::read(path)?, colors)
+    }
+}
+
+impl World {
+    /// Writes every tile between two corners, including both, as a MagicaVoxel model.
+    /// Each tile takes its color from `colors`, and air and unloaded tiles are left empty.
+    pub fn export_vox(
+        &self,
+        a: IVec3,
+        b: IVec3,
+        colors: &ColorTable,
+        registry: &TileRegistry,
+    ) -> Result<Vec<u8>, VoxError> {
+        let (min, max) = (a.min(b), a.max(b));
+        let region = max - min + IVec3::ONE;
+        if region.cmpgt(IVec3::splat(MAX_VOX_SIZE)).any() {
+            return Err(VoxError::RegionTooLarge(region));
+        }
+        let size = IVec3::new(region.x, region.z, region.y);
+
+        let mut palette: Vec<[u8; 3]> = vec![];
+        let mut voxels = vec![];
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let pos = IVec3::new(x, y, z);
+                    let Some(tile) = self.get_tile(min + from_vox(pos, size)) else {
+                        continue;
+                    };
+                    let color = colors
+                        .color(tile)
+                        .ok_or_else(|| VoxError::UnmappedTile(registry.get(tile).name.clone()))?;
+                    let index = match palette.iter().position(|c| *c == color) {
+                        Some(index) => index,
+                        None if palette.len() < 255 => {
+                            palette.push(color);
+                            palette.len() - 1
+                        }
+                        None => return Err(VoxError::TooManyColors),
+                    };
+                    voxels.extend([x as u8, y as u8, z as u8, index as u8 + 1]);
+                }
+            }
+        }
+
+        let mut size_chunk = vec![];
+        for v in size.to_array() {
+            size_chunk.extend((v as u32).to_le_bytes());
+        }
+        let mut xyzi = ((voxels.len() / 4) as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels);
+        let mut rgba = vec![0; 256 * 4];
+        for (i, color) in palette.iter().enumerate() {
+            rgba[i * 4..i * 4 + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+
+        let mut children = vec![];
+        write_chunk(&mut children, b"SIZE", &size_chunk, &[]);
+        write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        write_chunk(&mut out, b"MAIN", &[], &children);
+        Ok(out)
+    }
+
+    pub fn save_vox(
+        &self,
+        path: impl AsRef<Path>,
+        a: IVec3,
+        b: IVec3,
+        colors: &ColorTable,
+        registry: &TileRegistry,
+    ) -> Result<(), VoxError> {
+        fs::write(path, self.export_vox(a, b, colors, registry)?)?;
+        Ok(())
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_le_bytes());
+    out.extend((children.len() as u32).to_le_bytes());
+    out.extend(content);
+    out.extend(children);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VoxError> {
+        if self.bytes.len() < len {
+            return Err(VoxError::Corrupt);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, VoxError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a chunk, returning its id, its content and its children.
+    fn chunk(&mut self) -> Result<(&'a [u8], Reader<'a>, Reader<'a>), VoxError> {
+        let id = self.take(4)?;
+        let content = self.u32()? as usize;
+        let children = self.u32()? as usize;
+        Ok((
+            id,
+            Reader {
+                bytes: self.take(content)?,
+            },
+            Reader {
+                bytes: self.take(children)?,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, tile::TileDef};
+
+    fn registry() -> TileRegistry {
+        let mut registry = TileRegistry::new();
+        for name in ["stone", "dirt", "grass"] {
+            registry.register(TileDef::new(name)).unwrap();
+        }
+        registry
+    }
+
+    fn colors(registry: &TileRegistry) -> ColorTable {
+        ColorTable::new()
+            .with_color([128, 128, 128], registry.id("stone").unwrap())
+            .with_color([120, 80, 40], registry.id("dirt").unwrap())
+            .with_color([60, 180, 60], registry.id("grass").unwrap())
+    }
+
+    #[test]
+    fn export_then_import() {
+        let registry = registry();
+        let [stone, dirt, grass] = ["stone", "dirt", "grass"].map(|n| registry.id(n));
+        let mut world = World::new(0);
+        world.insert_chunk(IVec3::ZERO, Chunk::new());
+        // A column along y and a row along z, so a mixed up axis shows.
+        let min = IVec3::new(2, 3, 4);
+        world.set_tile(min, stone);
+        world.set_tile(min + IVec3::Y, dirt);
+        world.set_tile(min + IVec3::Y * 2, grass);
+        world.set_tile(min + IVec3::Z * 3, dirt);
+        world.set_tile(min + IVec3::new(1, 0, 3), grass);
+
+        let max = min + IVec3::new(1, 2, 3);
+        let bytes = world
+            .export_vox(min, max, &colors(&registry), &registry)
+            .unwrap();
+        let structure = Structure::from_vox(&bytes, &colors(&registry)).unwrap();
+
+        assert_eq!(structure.size(), max - min + IVec3::ONE);
+        for x in 0..=1 {
+            for y in 0..=2 {
+                for z in 0..=3 {
+                    let loc = IVec3::new(x, y, z);
+                    assert_eq!(structure.get(loc), world.get_tile(min + loc), "at {loc}");
+                }
+            }
+        }
+    }
+
+    /// Writes a model of one column of voxels standing up along MagicaVoxel's z axis.
+    fn column(palette: Option<&[u8]>, colors: [u8; 3]) -> Vec<u8> {
+        let mut size = vec![];
+        for v in [1u32, 1, 3] {
+            size.extend(v.to_le_bytes());
+        }
+        let mut xyzi = 3u32.to_le_bytes().to_vec();
+        for (z, color) in colors.into_iter().enumerate() {
+            xyzi.extend([0, 0, z as u8, color]);
+        }
+
+        let mut children = vec![];
+        write_chunk(&mut children, b"SIZE", &size, &[]);
+        write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        if let Some(palette) = palette {
+            write_chunk(&mut children, b"RGBA", palette, &[]);
+        }
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        write_chunk(&mut out, b"MAIN", &[], &children);
+        out
+    }
+
+    #[test]
+    fn z_points_up() {
+        let registry = registry();
+        let mut palette = vec![0; 256 * 4];
+        palette[..12].copy_from_slice(&[128, 128, 128, 255, 120, 80, 40, 255, 60, 180, 60, 255]);
+
+        let structure =
+            Structure::from_vox(&column(Some(&palette), [1, 2, 3]), &colors(&registry)).unwrap();
+        assert_eq!(structure.size(), IVec3::new(1, 3, 1));
+        let tiles = (0..3).map(|y| structure.get(IVec3::new(0, y, 0)));
+        assert!(tiles.eq(["stone", "dirt", "grass"].map(|n| registry.id(n))));
+    }
+
+    #[test]
+    fn missing_palette_uses_the_default() {
+        let registry = registry();
+        let table = ColorTable::new()
+            .with_color([255, 255, 255], registry.id("stone").unwrap())
+            .with_color([255, 0, 0], registry.id("dirt").unwrap())
+            .with_color([17, 17, 17], registry.id("grass").unwrap());
+
+        // White, the brightest red of the ramps, and the darkest gray.
+        let structure = Structure::from_vox(&column(None, [1, 216, 255]), &table).unwrap();
+        let tiles = (0..3).map(|y| structure.get(IVec3::new(0, y, 0)));
+        assert!(tiles.eq(["stone", "dirt", "grass"].map(|n| registry.id(n))));
+    }
+}
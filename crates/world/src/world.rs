@@ -1,11 +1,511 @@
-// use glam::IVec3;
-// use wgpu::naga::FastHashMap;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-// use crate::chunk::Chunk;
+use glam::IVec3;
+use wgpu::naga::{FastHashMap, FastHashSet};
 
-// #[derive(Default)]
-// pub struct World {
-//     chunks: FastHashMap<IVec3, Chunk>,
+use render::{mesh::Mesh, renderer::Renderer};
 
-//     seed: u64,
-// }
+use crate::{
+    chunk::{neighbor_offset, Chunk, ChunkNeighbors, CHUNK_SIZE, NEIGHBORHOOD},
+    gen::{biome::Biome, PendingTile, WorldGenerator},
+    region::{self, Region},
+    save::{SaveError, WorldMeta, FORMAT_VERSION, META_FILE},
+    tile::{TileId, TileRegistry, TileState},
+};
+
+/// Chunks are shared behind an [`Arc`] so they can be handed to worker threads cheaply.
+/// Mutating a chunk which is still shared copies it first.
+#[derive(Default)]
+pub struct World {
+    chunks: FastHashMap<IVec3, Arc<Chunk>>,
+
+    seed: u64,
+
+    save_dir: Option<PathBuf>,
+
+    generator: Option<Arc<dyn WorldGenerator>>,
+
+    /// Tiles generated into chunks which don't exist yet, by the chunk they belong in.
+    /// They are saved per region, since the chunk that placed them may never generate again.
+    pending: FastHashMap<IVec3, Vec<PendingTile>>,
+    /// The regions whose saved pending tiles were read into [`Self::pending`].
+    pending_regions: FastHashSet<IVec3>,
+    /// Loaded chunks whose mesh may be out of date, see [`Self::take_dirty`]. Tracking them here
+    /// instead of in the chunks leaves chunks still shared with a worker uncopied.
+    dirty: FastHashSet<IVec3>,
+}
+
+impl World {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            chunks: FastHashMap::default(),
+            seed,
+            save_dir: None,
+            generator: None,
+            pending: FastHashMap::default(),
+            pending_regions: FastHashSet::default(),
+            dirty: FastHashSet::default(),
+        }
+    }
+
+    pub fn with_generator(mut self, generator: impl WorldGenerator + 'static) -> Self {
+        self.set_generator(generator);
+        self
+    }
+
+    /// Sets the generator used to fill new chunks.
+    /// It should be built from this world's seed, so the world regenerates the same way.
+    pub fn set_generator(&mut self, generator: impl WorldGenerator + 'static) {
+        self.generator = Some(Arc::new(generator));
+    }
+
+    pub fn generator(&self) -> Option<&Arc<dyn WorldGenerator>> {
+        self.generator.as_ref()
+    }
+
+    /// The biome at a world position, if the world's generator uses biomes.
+    pub fn biome_at(&self, pos: IVec3) -> Option<&Biome> {
+        self.generator.as_ref()?.biome_at(pos)
+    }
+
+    /// Generates a chunk, replacing it if it was loaded.
+    /// Without a generator the chunk is left empty.
+    pub fn generate_chunk(&mut self, pos: IVec3, registry: &TileRegistry) -> &mut Chunk {
+        let mut chunk = Chunk::new();
+        let pending = match &self.generator {
+            Some(generator) => generator.generate(pos, &mut chunk),
+            None => vec![],
+        };
+
+        self.insert_chunk(pos, chunk);
+        self.apply_pending(pos, registry);
+        self.place_pending(pending);
+        self.chunk_mut(pos).unwrap()
+    }
+
+    /// Places tiles generated outside of their chunk. Tiles in loaded chunks are set right away,
+    /// while the rest wait until their chunk is generated or loaded, and are placed by
+    /// [`Self::apply_pending`]. Returns the positions of the tiles that were set.
+    pub fn place_pending(&mut self, tiles: impl IntoIterator<Item = PendingTile>) -> Vec<IVec3> {
+        let mut placed = vec![];
+        for tile in tiles {
+            match self.set_loaded_tile(tile.pos, Some(tile.tile)) {
+                true => placed.push(tile.pos),
+                false => {
+                    let (chunk, _) = Self::split_pos(tile.pos);
+                    self.pending.entry(chunk).or_default().push(tile);
+                }
+            }
+        }
+        placed
+    }
+
+    /// Places the tiles waiting for a chunk which was just inserted, returning their positions.
+    /// Tiles saved in an earlier session are read from the chunk's region first.
+    pub fn apply_pending(&mut self, chunk: IVec3, registry: &TileRegistry) -> Vec<IVec3> {
+        if let Err(e) = self.read_pending(Region::locate(chunk).0, registry) {
+            log::error!("Reading pending tiles for chunk {chunk} failed with error: {e}");
+        }
+        let tiles = self.pending.remove(&chunk).unwrap_or_default();
+        self.place_pending(tiles)
+    }
+
+    /// Merges the saved pending tiles of a region into [`Self::pending`], once per region.
+    fn read_pending(&mut self, region: IVec3, registry: &TileRegistry) -> Result<(), SaveError> {
+        let Some(dir) = &self.save_dir else {
+            return Ok(());
+        };
+        if self.pending_regions.contains(&region) {
+            return Ok(());
+        }
+
+        for tile in region::read_pending(dir, region, registry)? {
+            let (chunk, _) = Self::split_pos(tile.pos);
+            // Tiles for a chunk that has since been loaded already made it in.
+            if !self.is_loaded(chunk) {
+                self.pending.entry(chunk).or_default().push(tile);
+            }
+        }
+        self.pending_regions.insert(region);
+        Ok(())
+    }
+
+    /// Loads a chunk from disk if it was saved, otherwise generates it.
+    pub fn load_or_generate(
+        &mut self,
+        pos: IVec3,
+        registry: &TileRegistry,
+    ) -> Result<&mut Chunk, SaveError> {
+        if self.save_dir.is_none() || !self.load_chunk(pos, registry)? {
+            self.generate_chunk(pos, registry);
+        }
+        Ok(self.chunk_mut(pos).unwrap())
+    }
+
+    /// Creates a new, empty world saved in `dir`.
+    pub fn create(dir: impl AsRef<Path>, seed: u64) -> Result<Self, SaveError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        WorldMeta::new(seed).write(dir)?;
+
+        let mut world = Self::new(seed);
+        world.save_dir = Some(dir.to_path_buf());
+        Ok(world)
+    }
+
+    /// Opens the world saved in `dir`. No chunks are loaded until [`Self::load_chunk`] is called.
+    /// A world saved in an older version of the format is upgraded to the current one first.
+    pub fn open(dir: impl AsRef<Path>, registry: &TileRegistry) -> Result<Self, SaveError> {
+        let dir = dir.as_ref();
+        let meta = WorldMeta::read(dir)?;
+        if meta.version < FORMAT_VERSION {
+            log::info!(
+                "Upgrading world from format version {} to {FORMAT_VERSION}",
+                meta.version
+            );
+            region::upgrade(dir, meta.version, registry)?;
+            WorldMeta::new(meta.seed).write(dir)?;
+        }
+
+        let mut world = Self::new(meta.seed);
+        world.save_dir = Some(dir.to_path_buf());
+        Ok(world)
+    }
+
+    /// Opens the world saved in `dir`, creating it with `seed` if there is no world there yet.
+    pub fn open_or_create(
+        dir: impl AsRef<Path>,
+        seed: u64,
+        registry: &TileRegistry,
+    ) -> Result<Self, SaveError> {
+        let dir = dir.as_ref();
+        match dir.join(META_FILE).exists() {
+            true => Self::open(dir, registry),
+            false => Self::create(dir, seed),
+        }
+    }
+
+    pub fn save_dir(&self) -> Option<&Path> {
+        self.save_dir.as_deref()
+    }
+
+    /// Loads a chunk from disk, returning whether it had been saved.
+    /// A chunk which is already loaded is replaced by the saved one.
+    pub fn load_chunk(&mut self, pos: IVec3, registry: &TileRegistry) -> Result<bool, SaveError> {
+        let dir = self.save_dir.as_ref().ok_or(SaveError::NoSaveDirectory)?;
+        let Some(chunk) = region::load_chunk(dir, pos, registry)? else {
+            return Ok(false);
+        };
+        self.insert_chunk(pos, chunk);
+        self.apply_pending(pos, registry);
+        Ok(true)
+    }
+
+    /// Saves every loaded chunk, along with the world's metadata.
+    pub fn save(&mut self, registry: &TileRegistry) -> Result<(), SaveError> {
+        self.save_chunks(self.chunks.keys().copied().collect::<Vec<_>>(), registry)
+    }
+
+    /// Saves the given loaded chunks, along with the world's metadata and every pending tile.
+    ///
+    /// Pending tiles of regions without any other loaded chunk are dropped from memory once they are
+    /// saved, and read back when a chunk of that region is loaded again.
+    pub fn save_chunks(
+        &mut self,
+        chunks: impl IntoIterator<Item = IVec3>,
+        registry: &TileRegistry,
+    ) -> Result<(), SaveError> {
+        let dir = self.save_dir.as_ref().ok_or(SaveError::NoSaveDirectory)?;
+        WorldMeta::new(self.seed).write(dir)?;
+
+        // Group the chunks by region so each region file is only rewritten once.
+        let saved = chunks.into_iter().collect::<FastHashSet<_>>();
+        let mut regions: FastHashMap<IVec3, Vec<(usize, &Chunk)>> = FastHashMap::default();
+        for pos in &saved {
+            if let Some(chunk) = self.chunks.get(pos) {
+                let pos = *pos;
+                let (region, index) = Region::locate(pos);
+                regions
+                    .entry(region)
+                    .or_default()
+                    .push((index, chunk.as_ref()));
+            }
+        }
+
+        for (region_pos, chunks) in regions {
+            let path = Region::path(dir, region_pos);
+            let mut region = Region::read(&path)?;
+            for (index, chunk) in chunks {
+                region.set(index, region::encode_chunk(chunk, registry)?);
+            }
+            region.write(&path)?;
+        }
+
+        self.save_pending(&saved, registry)
+    }
+
+    /// Writes the pending tiles of every region which holds some or had them read, then forgets the
+    /// ones no loaded chunk outside `saved` could still need.
+    fn save_pending(
+        &mut self,
+        saved: &FastHashSet<IVec3>,
+        registry: &TileRegistry,
+    ) -> Result<(), SaveError> {
+        let mut regions = self.pending_regions.clone();
+        regions.extend(self.pending.keys().map(|chunk| Region::locate(*chunk).0));
+        for region in &regions {
+            self.read_pending(*region, registry)?;
+        }
+
+        let mut tiles: FastHashMap<IVec3, Vec<PendingTile>> = FastHashMap::default();
+        for (chunk, pending) in &self.pending {
+            tiles
+                .entry(Region::locate(*chunk).0)
+                .or_default()
+                .extend(pending);
+        }
+
+        let dir = self.save_dir.as_ref().ok_or(SaveError::NoSaveDirectory)?;
+        let in_use = self
+            .chunks
+            .keys()
+            .filter(|chunk| !saved.contains(chunk))
+            .map(|chunk| Region::locate(*chunk).0)
+            .collect::<FastHashSet<_>>();
+        for region in regions {
+            region::write_pending(dir, region, tiles.get(&region).map_or(&[], |t| t), registry)?;
+            if !in_use.contains(&region) {
+                self.pending_regions.remove(&region);
+                self.pending
+                    .retain(|chunk, _| Region::locate(*chunk).0 != region);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Splits a world position into the position of its chunk, and its location inside that chunk.
+    pub fn split_pos(pos: IVec3) -> (IVec3, [usize; 3]) {
+        let size = CHUNK_SIZE as i32;
+        let chunk = pos.div_euclid(IVec3::splat(size));
+        let local = pos.rem_euclid(IVec3::splat(size));
+        (
+            chunk,
+            [local.x as usize, local.y as usize, local.z as usize],
+        )
+    }
+
+    /// Returns the world position of the first tile in a chunk.
+    pub fn chunk_origin(chunk: IVec3) -> IVec3 {
+        chunk * CHUNK_SIZE as i32
+    }
+
+    /// Returns every chunk whose mesh can change when the tile at `pos` changes.
+    /// This is the chunk holding the tile, plus any neighbors it borders, including those across an
+    /// edge or corner whose ambient occlusion it affects.
+    pub fn affected_chunks(pos: IVec3) -> Vec<IVec3> {
+        let (chunk, loc) = Self::split_pos(pos);
+        let mut chunks = vec![chunk];
+        for i in 0..NEIGHBORHOOD {
+            let offset = neighbor_offset(i);
+            let edge = (0..3).all(|axis| match offset[axis] {
+                -1 => loc[axis] == 0,
+                1 => loc[axis] == CHUNK_SIZE - 1,
+                _ => true,
+            });
+            if edge && offset != [0, 0, 0] {
+                chunks.push(chunk + IVec3::from(offset));
+            }
+        }
+        chunks
+    }
+
+    pub fn get_tile(&self, pos: IVec3) -> Option<TileId> {
+        let (chunk, loc) = Self::split_pos(pos);
+        self.chunks.get(&chunk)?.get(loc)
+    }
+
+    /// Sets a tile, creating the chunk that holds it if it isn't loaded.
+    /// Every loaded chunk whose mesh depends on the tile is marked dirty.
+    pub fn set_tile(&mut self, pos: IVec3, tile: Option<TileId>) {
+        let (chunk, loc) = Self::split_pos(pos);
+        self.chunk_or_insert(chunk).set(loc, tile);
+        self.mark_dirty(pos);
+    }
+
+    /// Sets a tile if its chunk is loaded, returning whether it was set. Unlike [`Self::set_tile`],
+    /// chunks that aren't loaded yet are left alone, so they are still generated or read from the
+    /// save when they load.
+    pub fn set_loaded_tile(&mut self, pos: IVec3, tile: Option<TileId>) -> bool {
+        if !self.is_loaded(Self::split_pos(pos).0) {
+            return false;
+        }
+        self.set_tile(pos, tile);
+        true
+    }
+
+    /// The state of the tile at a position, or the default state if it isn't loaded.
+    pub fn get_state(&self, pos: IVec3) -> TileState {
+        let (chunk, loc) = Self::split_pos(pos);
+        self.chunks
+            .get(&chunk)
+            .map_or(TileState::default(), |chunk| chunk.state(loc))
+    }
+
+    /// Sets the state of the tile at a position, if it is loaded. Setting the tile resets its state,
+    /// so set the state afterwards.
+    pub fn set_state(&mut self, pos: IVec3, state: TileState) {
+        let (chunk, loc) = Self::split_pos(pos);
+        let Some(chunk) = self.chunk_mut(chunk) else {
+            return;
+        };
+        chunk.set_state(loc, state);
+        self.mark_dirty(pos);
+    }
+
+    /// Marks every loaded chunk from [`Self::affected_chunks`] dirty, after the tile or light at
+    /// `pos` changed.
+    pub fn mark_dirty(&mut self, pos: IVec3) {
+        for chunk in Self::affected_chunks(pos) {
+            if self.is_loaded(chunk) {
+                self.dirty.insert(chunk);
+            }
+        }
+    }
+
+    /// Returns every dirty chunk, marking them clean. The caller is expected to rebuild their meshes.
+    /// New chunks start out dirty, and so does every chunk borrowed mutably since the last call.
+    pub fn take_dirty(&mut self) -> Vec<IVec3> {
+        self.dirty.drain().collect()
+    }
+
+    pub fn chunk(&self, pos: IVec3) -> Option<&Chunk> {
+        self.chunks.get(&pos).map(|c| c.as_ref())
+    }
+
+    /// Returns a shared handle to a chunk, which stays unchanged by later edits to the world.
+    pub fn shared_chunk(&self, pos: IVec3) -> Option<Arc<Chunk>> {
+        self.chunks.get(&pos).cloned()
+    }
+
+    pub fn chunk_mut(&mut self, pos: IVec3) -> Option<&mut Chunk> {
+        let chunk = self.chunks.get_mut(&pos)?;
+        self.dirty.insert(pos);
+        Some(Arc::make_mut(chunk))
+    }
+
+    pub fn chunk_or_insert(&mut self, pos: IVec3) -> &mut Chunk {
+        self.dirty.insert(pos);
+        Arc::make_mut(self.chunks.entry(pos).or_default())
+    }
+
+    pub fn insert_chunk(&mut self, pos: IVec3, chunk: Chunk) -> Option<Chunk> {
+        self.dirty.insert(pos);
+        self.chunks
+            .insert(pos, Arc::new(chunk))
+            .map(Arc::unwrap_or_clone)
+    }
+
+    pub fn remove_chunk(&mut self, pos: IVec3) -> Option<Chunk> {
+        self.dirty.remove(&pos);
+        self.chunks.remove(&pos).map(Arc::unwrap_or_clone)
+    }
+
+    pub fn is_loaded(&self, pos: IVec3) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec3, &Chunk)> {
+        self.chunks
+            .iter()
+            .map(|(pos, chunk)| (*pos, chunk.as_ref()))
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = (IVec3, &mut Chunk)> {
+        self.dirty.extend(self.chunks.keys().copied());
+        self.chunks
+            .iter_mut()
+            .map(|(pos, chunk)| (*pos, Arc::make_mut(chunk)))
+    }
+
+    pub fn neighbors(&self, pos: IVec3) -> ChunkNeighbors<'_> {
+        ChunkNeighbors::from_fn(|offset| self.chunk(pos + IVec3::from(offset)))
+    }
+
+    /// Meshes a loaded chunk at its place in the world, taking its neighbors into account.
+    pub fn mesh_chunk(
+        &self,
+        pos: IVec3,
+        renderer: &Renderer,
+        registry: &TileRegistry,
+    ) -> Option<Mesh> {
+        let chunk = self.chunk(pos)?;
+        Some(chunk.mesh(
+            renderer,
+            registry,
+            &self.neighbors(pos),
+            Self::chunk_origin(pos).as_vec3().to_array(),
+        ))
+    }
+
+    /// The number of bytes used by every loaded chunk.
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.values().map(|c| c.memory_usage()).sum()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: TileId = TileId(0);
+
+    #[test]
+    fn set_tile_creates_chunks() {
+        let mut world = World::new(0);
+        let pos = IVec3::new(40, -3, 5);
+        world.set_tile(pos, Some(STONE));
+        assert!(world.is_loaded(World::split_pos(pos).0));
+        assert_eq!(world.get_tile(pos), Some(STONE));
+    }
+
+    #[test]
+    fn set_loaded_tile_skips_unloaded_chunks() {
+        let mut world = World::new(0);
+        let pos = IVec3::new(40, -3, 5);
+        assert!(!world.set_loaded_tile(pos, Some(STONE)));
+        assert!(!world.is_loaded(World::split_pos(pos).0));
+
+        world.insert_chunk(World::split_pos(pos).0, Chunk::new());
+        assert!(world.set_loaded_tile(pos, Some(STONE)));
+        assert_eq!(world.get_tile(pos), Some(STONE));
+    }
+
+    #[test]
+    fn take_dirty_leaves_shared_chunks_alone() {
+        let mut world = World::new(0);
+        world.insert_chunk(IVec3::ZERO, Chunk::new());
+        assert_eq!(world.take_dirty(), vec![IVec3::ZERO]);
+        assert!(world.take_dirty().is_empty());
+
+        // A worker meshing the chunk still holds it.
+        let shared = world.shared_chunk(IVec3::ZERO).unwrap();
+        world.mark_dirty(IVec3::ZERO);
+        assert_eq!(world.take_dirty(), vec![IVec3::ZERO]);
+        assert!(Arc::ptr_eq(
+            &shared,
+            &world.shared_chunk(IVec3::ZERO).unwrap()
+        ));
+    }
+}
@@ -1,30 +1,47 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, sync::Arc};
 
 use app::{
     events::AppEvent,
     frame::UpdateFrame,
+    input::{MOUSE_LEFT, MOUSE_RIGHT},
     scene::{Scene, SceneEvent},
     window_extension::WindowExtensions,
     App,
 };
-use glam::Vec3;
-use noise::{NoiseFn, Perlin};
+use glam::{IVec3, Vec3};
 use render::{
     camera::Camera,
     dir_light::DirectionalLight,
     frame::Frame,
+    frustum::CullStats,
     light::Light,
     material::{DefaultMaterial, Material, UnlitMaterial},
     mesh::{builder::MeshBuilder, render::RenderMesh, Mesh},
     renderer::Renderer,
-    texture::Texture,
+    texture_array::TextureArrayBuilder,
     util::color,
 };
-use wgpu::{include_wgsl, Color};
+use wgpu::{include_wgsl, naga::FastHashMap, Color};
 use winit::keyboard::KeyCode;
 use world::{
-    chunk::{Chunk, CHUNK_SIZE},
-    tile::Tile,
+    chunk::CHUNK_SIZE,
+    edit::{Brush, ChangeSet, EditHistory, Voxel},
+    fluid::FluidSim,
+    gen::{
+        biome::{BiomeDecorations, BiomeMap, BiomeSurface, BiomeTerrain},
+        caves::Caves,
+        ores::{Ore, Ores},
+        structures::{StructurePlacement, Structures},
+        StagedGenerator,
+    },
+    mesher::MeshMode,
+    physics::{Aabb, Body},
+    raycast::RaycastHit,
+    streamer::ChunkStreamer,
+    structure::Structure,
+    tile::{TileId, TileRegistry, TileState},
+    vox::ColorTable,
+    world::World,
 };
 
 fn main() -> anyhow::Result<()> {
@@ -40,11 +57,51 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+const SAVE_DIR: &str = "saves/world";
+/// Where the area around the targeted tile is exported to as a MagicaVoxel model.
+const EXPORT_PATH: &str = "saves/export.vox";
+/// How far the exported area reaches from the targeted tile.
+const EXPORT_RADIUS: i32 = 8;
+const TILE_TEXTURE_DIR: &str = "assets/textures/tiles";
+const TILE_TEXTURE_SIZE: u32 = 16;
+const RENDER_DISTANCE: i32 = 8;
+const VERTICAL_RENDER_DISTANCE: i32 = 2;
+/// How many chunks away chunks start losing detail.
+const LOD_DISTANCE: f32 = 3.0;
+/// How far away tiles can be broken and placed.
+const REACH: f32 = 8.0;
+/// The radius of the sphere filled or carved out around the targeted tile.
+const BRUSH_RADIUS: f32 = 3.5;
+
+const PLAYER_HALF_WIDTH: f32 = 0.3;
+const PLAYER_HEIGHT: f32 = 1.8;
+const EYE_HEIGHT: f32 = 1.62;
+const WALK_SPEED: f32 = 4.3;
+const JUMP_SPEED: f32 = 8.4;
+
 pub struct TestScene {
-    meshes: Vec<Mesh>,
+    world: World,
+    registry: Arc<TileRegistry>,
+    streamer: ChunkStreamer,
+    loading: bool,
+    fluids: FluidSim,
+    history: EditHistory,
+    /// Maps the colors of MagicaVoxel models to tiles.
+    vox_colors: ColorTable,
+
+    placing: TileId,
+
+    meshes: FastHashMap<IVec3, Mesh>,
     unlit_meshes: Vec<Mesh>,
+    /// How many meshes the last frame drew and culled.
+    cull_stats: CullStats,
+    /// Whether chunks hidden behind terrain are culled.
+    occlusion_culling: bool,
 
     camera: Camera,
+    /// The player's body, which follows the camera while flying.
+    body: Body,
+    walking: bool,
 
     bound: bool,
 
@@ -60,11 +117,19 @@ impl TestScene {
             ..Default::default()
         };
 
-        let mut meshes = vec![];
         let mut unlit_meshes = vec![];
 
         window.lock_cursor(true);
 
+        let mut textures = TextureArrayBuilder::new(TILE_TEXTURE_SIZE);
+        textures
+            .add_dir(TILE_TEXTURE_DIR)
+            .expect("Tile textures should be readable");
+
+        let mut registry = TileRegistry::from_toml(include_str!("../assets/tiles.toml"))
+            .expect("Tile definitions should be valid");
+        registry.bind_textures(textures.layers());
+
         let material = DefaultMaterial::new(
             renderer,
             &renderer
@@ -75,7 +140,7 @@ impl TestScene {
                 Vec3::new(0.0, CHUNK_SIZE as f32, 0.0),
                 color(1.0, 0.576, 0.184),
             )],
-            Texture::from_bytes(renderer, include_bytes!("../assets/textures/grid.png")).unwrap(),
+            textures.build(renderer),
         );
         let unlit_material = UnlitMaterial::new(
             renderer,
@@ -88,45 +153,96 @@ impl TestScene {
             .with_added([0.0, 0.0, 0.0], 0..6)
             .build(renderer)]);
 
-        let perlin = Perlin::new(0);
-
-        for x in -1..=1 {
-            for y in -1..1 {
-                for z in -1..=1 {
-                    let mut chunk = Chunk::new();
-                    for dx in 0..CHUNK_SIZE {
-                        for dy in 0..CHUNK_SIZE {
-                            for dz in 0..CHUNK_SIZE {
-                                const FREQUENCY: f64 = 0.05;
-                                let value = perlin.get([
-                                    (dx as f64 + x as f64 * CHUNK_SIZE as f64) * FREQUENCY,
-                                    (dy as f64 + y as f64 * CHUNK_SIZE as f64) * FREQUENCY,
-                                    (dz as f64 + z as f64 * CHUNK_SIZE as f64) * FREQUENCY,
-                                ]);
-                                if value >= 0.2 {
-                                    chunk.set([dx, dy, dz], Some(Tile {}));
-                                }
-                            }
-                        }
-                    }
+        let tile = |name: &str| {
+            registry
+                .id(name)
+                .unwrap_or_else(|| panic!("{name} should be registered"))
+        };
+        let stone = tile("stone");
+        let structure = |src: &str| {
+            Arc::new(Structure::from_toml(src, &registry).expect("Structures should be valid"))
+        };
 
-                    meshes.push(chunk.mesh(
-                        renderer,
-                        [
-                            x as f32 * CHUNK_SIZE as f32,
-                            y as f32 * CHUNK_SIZE as f32,
-                            z as f32 * CHUNK_SIZE as f32,
-                        ],
-                    ));
-                }
-            }
+        // A broken save is left alone, so play continues in a fresh world that isn't saved.
+        let mut world = World::open_or_create(SAVE_DIR, 0, &registry).unwrap_or_else(|e| {
+            log::error!("Opening world failed with error: {e}, playing without saving");
+            World::new(0)
+        });
+        let seed = world.seed();
+        let biomes = Arc::new(
+            BiomeMap::from_toml(seed, include_str!("../assets/biomes.toml"), &registry)
+                .expect("Biome definitions should be valid"),
+        );
+        world.set_generator(
+            StagedGenerator::new(seed, BiomeTerrain::new(biomes.clone()), stone)
+                .with_biomes(biomes)
+                .with_stage(BiomeSurface { replaces: stone })
+                .with_stage(Caves::new(seed, 0.04, 0.08))
+                .with_stage(Ores::new().with_ore(Ore {
+                    tile: tile("coal_ore"),
+                    replaces: stone,
+                    veins: 12,
+                    size: 8,
+                    height: -64..16,
+                }))
+                .with_stage(BiomeDecorations)
+                .with_stage(
+                    Structures::new()
+                        .with_structure(StructurePlacement {
+                            structure: structure(include_str!("../assets/structures/tree.toml")),
+                            on: vec![tile("grass")],
+                            attempts: 3,
+                            chance: 0.5,
+                        })
+                        .with_structure(StructurePlacement {
+                            structure: structure(include_str!("../assets/structures/boulder.toml")),
+                            on: vec![tile("grass"), tile("stone")],
+                            attempts: 1,
+                            chance: 0.1,
+                        }),
+                ),
+        );
+
+        if let Some(biome) = world.biome_at(IVec3::ZERO) {
+            log::info!("Spawned in the {} biome", biome.name);
         }
 
+        let vox_colors =
+            ColorTable::from_toml(include_str!("../assets/vox_colors.toml"), &registry)
+                .expect("Vox colors should be valid");
+
+        let registry = Arc::new(registry);
+        let mut streamer = ChunkStreamer::new(
+            &world,
+            registry.clone(),
+            RENDER_DISTANCE,
+            VERTICAL_RENDER_DISTANCE,
+        );
+        streamer.lod_distance = Some(LOD_DISTANCE);
+
         Box::new(Self {
-            meshes,
+            world,
+            registry,
+            streamer,
+            loading: true,
+            fluids: FluidSim::new(),
+            history: EditHistory::new(),
+            vox_colors,
+
+            placing: stone,
+
+            meshes: FastHashMap::default(),
             unlit_meshes,
+            cull_stats: CullStats::default(),
+            occlusion_culling: true,
 
             camera,
+            body: Body::new(
+                camera.pos - Vec3::Y * EYE_HEIGHT,
+                PLAYER_HALF_WIDTH,
+                PLAYER_HEIGHT,
+            ),
+            walking: false,
 
             bound: true,
 
@@ -134,6 +250,60 @@ impl TestScene {
             unlit_material,
         })
     }
+
+    /// Sets a tile, as long as the chunk holding it is loaded and a placed tile doesn't replace
+    /// another one. Fluids can be placed over.
+    fn edit(&mut self, pos: IVec3, tile: Option<TileId>, state: TileState) {
+        let occupied = self
+            .world
+            .get_tile(pos)
+            .is_some_and(|t| self.registry.get(t).fluid.is_none());
+        if tile.is_some() && occupied {
+            return;
+        }
+
+        // The streamer remeshes every chunk this dirties on its next update.
+        let changes = self
+            .world
+            .apply_edit([(pos, Voxel::new(tile, state))], &self.registry);
+        self.edited(&changes);
+        self.history.record(changes);
+    }
+
+    /// Connects fences and wakes fluids around every tile an edit changed.
+    fn edited(&mut self, changes: &ChangeSet) {
+        for change in changes.changes() {
+            self.world.update_connections(change.pos, &self.registry);
+            self.fluids
+                .tile_changed(&self.world, &self.registry, change.pos);
+        }
+    }
+
+    /// The state to place the selected tile in against the face that was hit.
+    /// Tiles with an `axis` line it up with the face's normal, a `facing` turns them away from the
+    /// camera, and a `half` puts them at the top when placed against the underside of a tile.
+    fn placing_state(&self, hit: &RaycastHit) -> TileState {
+        let def = self.registry.get(self.placing);
+        let axis = match hit.normal.abs().to_array() {
+            [1, 0, 0] => "x",
+            [0, 0, 1] => "z",
+            _ => "y",
+        };
+        let forward = self.camera.forward();
+        let facing = match forward.x.abs() > forward.z.abs() {
+            true if forward.x > 0.0 => "right",
+            true => "left",
+            false if forward.z > 0.0 => "back",
+            false => "front",
+        };
+        let half = if hit.normal.y < 0 { "top" } else { "bottom" };
+
+        [("axis", axis), ("facing", facing), ("half", half)]
+            .into_iter()
+            .fold(TileState::default(), |state, (name, value)| {
+                def.with_property(state, name, value).unwrap_or(state)
+            })
+    }
 }
 
 impl Scene for TestScene {
@@ -148,6 +318,16 @@ impl Scene for TestScene {
             frame.window.lock_cursor(self.bound);
         }
 
+        // Switch between the simple and greedy mesher to compare them.
+        if input.just_pressed(KeyCode::KeyM) {
+            let mode = match self.streamer.mesh_mode() {
+                MeshMode::Simple => MeshMode::Greedy,
+                MeshMode::Greedy => MeshMode::Simple,
+            };
+            log::info!("Switched to the {mode:?} mesher");
+            self.streamer.set_mesh_mode(&mut self.world, mode);
+        }
+
         // If the mouse is locked, rotate the camera.
         if self.bound {
             let mouse_delta = input.mouse_delta();
@@ -163,28 +343,157 @@ impl Scene for TestScene {
             self.camera.yaw = self.camera.yaw.rem_euclid(2.0 * PI);
         }
 
+        // Switch between walking and flying through the world.
+        if input.just_pressed(KeyCode::KeyF) {
+            self.walking = !self.walking;
+            self.body.velocity = Vec3::ZERO;
+            log::info!(
+                "Switched to {}",
+                if self.walking { "walking" } else { "flying" }
+            );
+        }
+
+        // Switch occlusion culling on and off to compare them.
+        if input.just_pressed(KeyCode::KeyO) {
+            self.occlusion_culling = !self.occlusion_culling;
+            log::info!(
+                "Switched occlusion culling {}",
+                if self.occlusion_culling { "on" } else { "off" }
+            );
+        }
+
+        if input.just_pressed(KeyCode::KeyC) {
+            log::info!(
+                "Drew {} meshes, culled {}",
+                self.cull_stats.drawn,
+                self.cull_stats.culled
+            );
+        }
+
         // Move camera based on input.
         let f = self.camera.forward();
         let r = self.camera.right();
         let u = Vec3::Y;
 
-        let mut vel = Vec3::ZERO;
-
         let horizontal =
             input.key_vector(KeyCode::KeyW, KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD);
-        let vert = input.key_value(KeyCode::Space, KeyCode::ShiftLeft);
 
-        vel += f * horizontal.y;
-        vel += r * horizontal.x;
-        vel += u * vert;
+        if self.walking {
+            let mut speed = WALK_SPEED;
+            if input.pressed(KeyCode::ControlLeft) {
+                speed *= 1.5;
+            }
+            let wish = (f * horizontal.y + r * horizontal.x).normalize_or_zero() * speed;
+            self.body.velocity.x = wish.x;
+            self.body.velocity.z = wish.z;
+            if input.pressed(KeyCode::Space) {
+                self.body.jump(JUMP_SPEED);
+            }
 
-        if input.pressed(KeyCode::ControlLeft) {
-            vel *= 10.0;
+            self.body.update(&self.world, &self.registry, delta);
+            self.camera.pos = self.body.position + u * EYE_HEIGHT;
         } else {
-            vel *= 5.0;
+            let mut vel = Vec3::ZERO;
+            let vert = input.key_value(KeyCode::Space, KeyCode::ShiftLeft);
+
+            vel += f * horizontal.y;
+            vel += r * horizontal.x;
+            vel += u * vert;
+
+            if input.pressed(KeyCode::ControlLeft) {
+                vel *= 10.0;
+            } else {
+                vel *= 5.0;
+            }
+            vel *= delta;
+            self.camera.pos += vel;
+            self.body.position = self.camera.pos - u * EYE_HEIGHT;
+        }
+
+        // Scroll through the tiles to place.
+        let scroll = input.scroll().y;
+        if scroll != 0.0 {
+            let count = self.registry.len() as i32;
+            let id = (self.placing.0 as i32 + scroll.signum() as i32).rem_euclid(count);
+            self.placing = TileId(id as u16);
+            log::info!("Placing {}", self.registry.get(self.placing).name);
+        }
+
+        if input.just_pressed(KeyCode::KeyZ) {
+            match self.history.undo(&mut self.world, &self.registry) {
+                Ok(Some(changes)) => {
+                    let changes = changes.clone();
+                    log::info!("Undid {} tile changes", changes.len());
+                    self.edited(&changes);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Can't undo yet: {e}"),
+            }
+        } else if input.just_pressed(KeyCode::KeyY) {
+            match self.history.redo(&mut self.world, &self.registry) {
+                Ok(Some(changes)) => {
+                    let changes = changes.clone();
+                    log::info!("Redid {} tile changes", changes.len());
+                    self.edited(&changes);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Can't redo yet: {e}"),
+            }
+        }
+
+        // Break the targeted tile on left click, and place against the targeted face on right click.
+        // Fill a sphere around the targeted tile with B, or carve one out with G. Export the area
+        // around it with X.
+        if self.bound {
+            if let Some(hit) = self
+                .world
+                .raycast(self.camera.pos, self.camera.direction(), REACH)
+            {
+                if input.mouse_just_pressed(MOUSE_LEFT) {
+                    self.edit(hit.pos, None, TileState::default());
+                } else if input.mouse_just_pressed(MOUSE_RIGHT)
+                    && !self.body.aabb().intersects(&Aabb::tile(hit.adjacent()))
+                {
+                    let state = self.placing_state(&hit);
+                    self.edit(hit.adjacent(), Some(self.placing), state);
+                }
+
+                let brush = Brush::Sphere {
+                    center: hit.pos,
+                    radius: BRUSH_RADIUS,
+                };
+                let voxel = if input.just_pressed(KeyCode::KeyB) {
+                    Some(Voxel::new(Some(self.placing), self.placing_state(&hit)))
+                } else if input.just_pressed(KeyCode::KeyG) {
+                    Some(Voxel::AIR)
+                } else {
+                    None
+                };
+                if input.just_pressed(KeyCode::KeyX) {
+                    let reach = IVec3::splat(EXPORT_RADIUS);
+                    match self.world.save_vox(
+                        EXPORT_PATH,
+                        hit.pos - reach,
+                        hit.pos + reach,
+                        &self.vox_colors,
+                        &self.registry,
+                    ) {
+                        Ok(()) => {
+                            log::info!("Exported the area around {} to {EXPORT_PATH}", hit.pos)
+                        }
+                        Err(e) => log::error!("Exporting failed with error: {e}"),
+                    }
+                }
+
+                if let Some(voxel) = voxel {
+                    let changes = self.world.fill(&brush, voxel, &self.registry);
+                    self.edited(&changes);
+                    self.history.record(changes);
+                }
+            }
         }
-        vel *= delta;
-        self.camera.pos += vel;
+
+        self.fluids.update(&mut self.world, &self.registry, delta);
 
         frame.events.register(AppEvent::ApplyCamera(self.camera));
 
@@ -192,28 +501,65 @@ impl Scene for TestScene {
     }
 
     fn render(&mut self, frame: &mut Frame) {
+        // Load chunks around the camera, uploading any meshes the workers finished and edited chunks.
+        let update = self.streamer.update(&mut self.world, self.camera.pos);
+        for pos in update.unloaded {
+            self.meshes.remove(&pos);
+        }
+        for (pos, builder) in update.meshed {
+            self.meshes.insert(pos, builder.build(frame.renderer));
+        }
+
+        if self.loading && self.streamer.pending() == 0 {
+            self.loading = false;
+            log::info!(
+                "Loaded {} chunks using {} bytes",
+                self.world.chunk_count(),
+                self.world.memory_usage()
+            );
+        } else if self.streamer.pending() != 0 {
+            self.loading = true;
+        }
+
         // Update Uniforms
         self.material.update_uniforms(frame.renderer);
         self.unlit_material.update_uniforms(frame.renderer);
 
+        // Only draw meshes the camera can see, skipping chunks hidden behind terrain.
+        let frustum = self.camera.frustum(&frame.renderer.projection);
+        let visible = self
+            .occlusion_culling
+            .then(|| self.streamer.visible_chunks(self.camera.pos, &frustum));
+
         // Create Pass
         let mut pass = frame.pass(Color::BLACK);
         // Apply Materials To Pass
         self.material.apply(&mut pass);
 
-        for mesh in &self.meshes {
-            pass.render_mesh(mesh);
-        }
+        let meshes = self
+            .meshes
+            .iter()
+            .filter(|(pos, _)| {
+                visible
+                    .as_ref()
+                    .is_none_or(|visible| visible.contains(*pos))
+            })
+            .map(|(_, mesh)| mesh);
+        self.cull_stats = pass.render_visible(meshes, &frustum);
+        // Chunks hidden behind terrain count as culled too.
+        self.cull_stats.culled = self.meshes.len() - self.cull_stats.drawn;
 
         // Apply Unlit Material To Pass
         self.unlit_material.apply(&mut pass);
 
-        for mesh in &self.unlit_meshes {
-            pass.render_mesh(mesh);
-        }
+        self.cull_stats += pass.render_visible(&self.unlit_meshes, &frustum);
     }
 
     fn exit(&mut self) {
         log::info!("Exiting Game!");
+
+        if let Err(e) = self.world.save(&self.registry) {
+            log::error!("Saving world failed with error: {e}");
+        }
     }
 }